@@ -15,6 +15,151 @@ pub struct Ports {
     midi_input: InputPort<AtomPort>,
     left_audio_output: OutputPort<Audio>,
     right_audio_output: OutputPort<Audio>,
+    portamento_time: InputPort<Control>,
+    portamento_mode: InputPort<Control>,
+    glide_direction: InputPort<Control>,
+    min_note: InputPort<Control>,
+    max_note: InputPort<Control>,
+    fold_notes: InputPort<Control>,
+    left_trim: InputPort<Control>,
+    right_trim: InputPort<Control>,
+    reset_phase_on_trigger: InputPort<Control>,
+    free_wheeling: InputPort<Control>,
+    offline_quality: InputPort<Control>,
+    pan: InputPort<Control>,
+    pan_law: InputPort<Control>,
+    amp_keytrack: InputPort<Control>,
+    humanize: InputPort<Control>,
+    stereo_detune: InputPort<Control>,
+    dsf_variant: InputPort<Control>,
+    filter_cutoff: InputPort<Control>,
+    filter_resonance: InputPort<Control>,
+    filter_keytrack: InputPort<Control>,
+    invert_left: InputPort<Control>,
+    invert_right: InputPort<Control>,
+    env_mode: InputPort<Control>,
+    tuning_hz: InputPort<Control>,
+    tuner_tone: InputPort<Control>,
+    stack_same_note: InputPort<Control>,
+    bend_up_range: InputPort<Control>,
+    max_voices: InputPort<Control>,
+    steal_priority: InputPort<Control>,
+    decay_curve: InputPort<Control>,
+    delay_time: InputPort<Control>,
+    delay_feedback: InputPort<Control>,
+    delay_mix: InputPort<Control>,
+    ping_pong: InputPort<Control>,
+    lfo_rate: InputPort<Control>,
+    lfo_depth: InputPort<Control>,
+    aftertouch_to_vibrato: InputPort<Control>,
+    gate_threshold: InputPort<Control>,
+    gate_release: InputPort<Control>,
+    click: InputPort<Control>,
+    reverb_size: InputPort<Control>,
+    reverb_damp: InputPort<Control>,
+    reverb_mix: InputPort<Control>,
+    classic_mono: InputPort<Control>,
+    clip: OutputPort<Control>,
+    brightness_decay: InputPort<Control>,
+    cc_source: InputPort<Control>,
+    cc_dest: InputPort<Control>,
+    tilt: InputPort<Control>,
+    glide_bend: InputPort<Control>,
+    effective_brightness: OutputPort<Control>,
+    effective_gain: OutputPort<Control>,
+    strum: InputPort<Control>,
+    click_smooth: InputPort<Control>,
+    left_audio_input: InputPort<Audio>,
+    right_audio_input: InputPort<Audio>,
+    input_mix: InputPort<Control>,
+    release_counts: InputPort<Control>,
+    mute: InputPort<Control>,
+    host_bpm: InputPort<Control>,
+    lfo_sync: InputPort<Control>,
+    lfo_division: InputPort<Control>,
+    channel_1_gain: InputPort<Control>,
+    channel_2_gain: InputPort<Control>,
+    channel_3_gain: InputPort<Control>,
+    channel_4_gain: InputPort<Control>,
+    omni: InputPort<Control>,
+    lfo_retrigger: InputPort<Control>,
+    hpf_cutoff: InputPort<Control>,
+    max_noteons_per_block: InputPort<Control>,
+    correlation: OutputPort<Control>,
+    velocity_floor: InputPort<Control>,
+    choke_1: InputPort<Control>,
+    choke_1_low: InputPort<Control>,
+    choke_1_high: InputPort<Control>,
+    choke_2: InputPort<Control>,
+    choke_2_low: InputPort<Control>,
+    choke_2_high: InputPort<Control>,
+    soft_clip: InputPort<Control>,
+    knee: InputPort<Control>,
+    clip_symmetry: InputPort<Control>,
+    metronome: InputPort<Control>,
+    host_bar_beat: InputPort<Control>,
+    host_speed: InputPort<Control>,
+    release_layer: InputPort<Control>,
+    min_time: InputPort<Control>,
+    max_time: InputPort<Control>,
+    freeze: InputPort<Control>,
+    sub_level: InputPort<Control>,
+    sub_pan: InputPort<Control>,
+    noise_level: InputPort<Control>,
+    noise_pan: InputPort<Control>,
+    auto_glide: InputPort<Control>,
+    bend_down_range: InputPort<Control>,
+    velocity_to_sustain: InputPort<Control>,
+    cpu_throttle: InputPort<Control>,
+    cpu_budget: InputPort<Control>,
+    unison: InputPort<Control>,
+    unison_detune: InputPort<Control>,
+    detune_distribution: InputPort<Control>,
+    output_mode: InputPort<Control>,
+    mono_fold: InputPort<Control>,
+    self_osc: InputPort<Control>,
+    attack_seconds: OutputPort<Control>,
+    decay_seconds: OutputPort<Control>,
+    release_seconds: OutputPort<Control>,
+    low_velocity_boost: InputPort<Control>,
+    high_velocity_boost: InputPort<Control>,
+    decorrelation: InputPort<Control>,
+    end_threshold: InputPort<Control>,
+    unison_phase_spread: InputPort<Control>,
+    brightness_comp: InputPort<Control>,
+    velocity_to_pan: InputPort<Control>,
+    glide_octave_snap: InputPort<Control>,
+    env_time: InputPort<Control>,
+    env_ratio: InputPort<Control>,
+    raw_osc: InputPort<Control>,
+    phase_align: InputPort<Control>,
+    half_pedal: InputPort<Control>,
+    haas: InputPort<Control>,
+    brightness_slew: InputPort<Control>,
+    hold_last: InputPort<Control>,
+    velocity_humanize: InputPort<Control>,
+    pitch_env_amount: InputPort<Control>,
+    pitch_env_time: InputPort<Control>,
+    velocity_to_glide: InputPort<Control>,
+    follower_attack: InputPort<Control>,
+    follower_release: InputPort<Control>,
+    follower_duck: InputPort<Control>,
+    follower: OutputPort<Control>,
+    lfo_shape: InputPort<Control>,
+    bpm: OutputPort<Control>,
+    release_curve: InputPort<Control>,
+    arp: InputPort<Control>,
+    arp_rate: InputPort<Control>,
+    gate_length: InputPort<Control>,
+    // the surround ports come last, so a stereo build keeps the port indices of the others;
+    // their description is in surround.ttl, whose indices follow the last port of
+    // dsf_synth.ttl
+    #[cfg(feature = "surround")]
+    front_back: InputPort<Control>,
+    #[cfg(feature = "surround")]
+    rear_left_audio_output: OutputPort<Audio>,
+    #[cfg(feature = "surround")]
+    rear_right_audio_output: OutputPort<Audio>,
 }
 
 #[derive(FeatureCollection)]
@@ -22,20 +167,90 @@ pub struct Features<'a> {
     map: LV2Map<'a>,
 }
 
+// key of the pitch table in the saved state
+#[uri("https://github.com/Ninja-Koala/dsf-synth#pitch_table")]
+pub struct PitchTable;
+
 #[derive(URIDCollection)]
 pub struct URIDs {
     atom: AtomURIDCollection,
     midi: MidiURIDCollection,
     unit: UnitURIDCollection,
+    pitch_table: URID<PitchTable>,
+}
+
+// one detuned copy of a unison voice
+#[derive(Debug, Clone)]
+pub struct UnisonCopy {
+    ratio: f32,
+    phase: f32,
+    right_phase: f32,
+}
+
+// short delay of one channel of a voice, widening it by the precedence effect
+#[derive(Debug, Clone)]
+pub struct Haas {
+    buffer: Vec<f32>,
+    position: usize,
+    // delays the left channel instead of the right one
+    left: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct Tone {
+    note: u8,
+    // key that started the tone, before the note range mapped it to `note`
+    key: u8,
+    channel: wmidi::Channel,
     phase_increment: f32,
-    time_pressed: u32,
-    time_released: Option<u32>,
+    target_phase_increment: f32,
+    glide_ratio: f32,
+    glide_frames: u32,
+    time_pressed: u64,
+    time_released: Option<u64>,
     velocity: f32,
+    // level scaling from amplitude keytracking
+    amplitude: f32,
+    env_mode: EnvelopeMode,
+    // polyphonic aftertouch from 0 to 1
+    pressure: f32,
+    // per-voice variation of the attack and release times
+    attack_scale: f32,
+    release_scale: f32,
+    // scaling of the sustain level from velocity
+    sustain_scale: f32,
+    // equal power gains of the voice's stereo position, 1 on both sides when centred
+    left_pan: f32,
+    right_pan: f32,
+    haas: Option<Haas>,
     phase: f32,
+    // phase of the detuned copy rendered to the right channel
+    right_phase: f32,
+    // detuned copies that replace the single oscillator when unison is on; empty for a single
+    // voice, which then renders exactly as without unison, with no copy, normalisation or
+    // extra random numbers drawn
+    unison: Vec<UnisonCopy>,
+    // phase of the sine an octave below the note
+    sub_phase: f32,
+    // phase of the voice's own lfo, started at 0 on note-on, used when the lfo retriggers
+    lfo_phase: f32,
+    // value the voice's own lfo holds for the current cycle with the sample and hold shape
+    lfo_held: f32,
+    // choke group of the note, and the frame at which another note of the group cut it off
+    choke_group: Option<usize>,
+    choked_at: Option<u64>,
+    // held at its current envelope level by freeze, and whether a note-off arrived meanwhile
+    frozen: bool,
+    // frames spent frozen, by which the envelope runs behind the note's start
+    frozen_frames: u64,
+    release_pending: bool,
+    // released while the damper pedal held it, so lifting the pedal releases it
+    sustained: bool,
+    // cutoff scaling from filter keytracking
+    cutoff_scale: f32,
+    filter: SvfCoefficients,
+    left_filter: Svf,
+    right_filter: Svf,
 }
 
 #[derive(Debug, Clone)]
@@ -44,6 +259,316 @@ pub struct Adsr {
     decay: f32,
     sustain: f32,
     release: f32,
+    // 0 decays linearly, higher values bend the decay towards an exponential
+    decay_curve: f32,
+    // the same for the release
+    release_curve: f32,
+    // level below which a releasing envelope counts as finished, so that its tail ends
+    // cleanly instead of lingering near silence
+    end_level: f32,
+}
+
+// state of a state-variable filter in its trapezoidal form
+#[derive(Debug, Clone, Default)]
+pub struct Svf {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct SvfCoefficients {
+    a1: f32,
+    a2: f32,
+    a3: f32,
+    input_gain: f32,
+    // bounds the band state so a self-oscillating filter settles instead of running away
+    saturate: bool,
+}
+
+// stereo feedback delay on the voice sum
+#[derive(Debug, Clone)]
+pub struct Delay {
+    left: Vec<f32>,
+    right: Vec<f32>,
+    position: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Comb {
+    buffer: Vec<f32>,
+    position: usize,
+    filter_store: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Allpass {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+// freeverb style reverb: parallel damped combs followed by serial allpasses per channel,
+// with the right channel's delays slightly longer to decorrelate the two
+#[derive(Debug, Clone)]
+pub struct Reverb {
+    left_combs: Vec<Comb>,
+    right_combs: Vec<Comb>,
+    left_allpasses: Vec<Allpass>,
+    right_allpasses: Vec<Allpass>,
+}
+
+// two short allpass chains tuned differently, whose difference is added to one channel
+// and taken from the other to widen the output without changing its mono sum
+#[derive(Debug, Clone)]
+pub struct Decorrelator {
+    left_allpasses: Vec<Allpass>,
+    right_allpasses: Vec<Allpass>,
+}
+
+// first order lowpass state per channel, splitting the output into a low and a high band
+// for the tilt eq
+#[derive(Debug, Clone, Default)]
+pub struct Tilt {
+    left: f32,
+    right: f32,
+}
+
+// first order highpass state per channel, the last input and output of each
+#[derive(Debug, Clone, Default)]
+pub struct Highpass {
+    left_input: f32,
+    left_output: f32,
+    right_input: f32,
+    right_output: f32,
+}
+
+// click on every beat of the host transport, higher on the downbeat of each bar
+#[derive(Debug, Clone, Default)]
+pub struct Metronome {
+    // beat within the bar, from the host at the start of the block and advanced per sample
+    position: f64,
+    last_position: Option<f64>,
+    level: f32,
+    phase: f32,
+    phase_increment: f32,
+}
+
+// plays the held keys one at a time on steps of the host tempo, from the lowest key up, and
+// releases each note after the gate length of its step
+#[derive(Debug, Clone, Default)]
+pub struct Arpeggiator {
+    // held keys in the order they are played, with their channel and velocity
+    keys: Vec<(wmidi::Channel, wmidi::Note, wmidi::Velocity)>,
+    // index into the keys of the next step
+    position: usize,
+    // frame of the next step, while any key is held
+    next_step: Option<u64>,
+    // the note played by the last step and the frame it is released at
+    sounding: Option<(wmidi::Channel, wmidi::Note, u64)>,
+}
+
+// latches to 1 when the output clips, holds and then falls back to 0
+#[derive(Debug, Clone, Default)]
+pub struct ClipIndicator {
+    level: f32,
+    hold_frames: u32,
+}
+
+// noise gate on the summed output
+#[derive(Debug, Clone)]
+pub struct Gate {
+    // peak level of the input, decaying slowly enough to bridge zero crossings
+    detector: f32,
+    detector_decay: f32,
+    attack_step: f32,
+    gain: f32,
+}
+
+// envelope of the external input, with separate smoothing for rising and falling levels
+#[derive(Debug, Clone, Default)]
+pub struct EnvelopeFollower {
+    level: f32,
+}
+
+// xorshift generator, so that randomized features render reproducibly
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u32,
+}
+
+// sample type the voices are summed in, converted to f32 for the effects after the sum
+#[cfg(feature = "f64-mix")]
+type Mix = f64;
+#[cfg(not(feature = "f64-mix"))]
+type Mix = f32;
+
+// a control port value that differs from the one the previous block saw
+#[cfg(feature = "debug-automation")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutomationChange {
+    pub block: u64,
+    pub port: &'static str,
+    pub value: f32,
+}
+
+// the most recent control port changes, for seeing what the host actually sent
+#[cfg(feature = "debug-automation")]
+#[derive(Debug, Clone)]
+pub struct AutomationLog {
+    block: u64,
+    previous: Vec<f32>,
+    changes: std::collections::VecDeque<AutomationChange>,
+}
+
+// whether a voice is still on its way to the pitch of its note, and the unbent pitch in Hz
+// it has reached, without vibrato
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlideState {
+    pub gliding: bool,
+    pub pitch: f32,
+}
+
+// one-pole smoothing of a control value towards its target
+#[derive(Debug, Clone)]
+pub struct Smoother {
+    value: f32,
+    coefficient: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortamentoMode {
+    Off,
+    // glide only from a note that is still held
+    Fingered,
+    // always glide from the last played note
+    Free,
+    // glide from whichever voice was triggered or released last, cascading through chords
+    Cascade,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlideDirection {
+    Both,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfflineQuality {
+    Off,
+    // only while the host renders faster than real time
+    FreeWheeling,
+    Always,
+}
+
+// attenuation of each channel when panned to the center against panned fully to its side;
+// the output pan keeps the center at unity, so the sides are raised by as much instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanLaw {
+    ZeroDb,
+    Minus3Db,
+    Minus4_5Db,
+    Minus6Db,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeMode {
+    // sustains while the key is held and releases on note-off
+    Gate,
+    // runs attack and decay down to silence regardless of note-off
+    Trigger,
+}
+
+// the pitch a glide starts from while the wheel is bent; glides run on the unbent pitch with
+// the bend applied on top, so from the bent pitch the new note starts where the previous one
+// sounded, while from the unbent pitch it starts where the previous one would sound unbent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlideBend {
+    FromBent,
+    FromUnbent,
+}
+
+// note length of one lfo cycle when it is synced to the host tempo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoDivision {
+    Whole,
+    Half,
+    Quarter,
+    Eighth,
+    EighthTriplet,
+    Sixteenth,
+}
+
+// waveform of the vibrato lfo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    // a random value held for each cycle
+    SampleAndHold,
+}
+
+// how the detune of the unison copies is spread over the detune range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetuneDistribution {
+    Even,
+    Random,
+    // random, clustered around the centre
+    Gaussian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    Stereo,
+    // both outputs carry the same fold of the two channels, with the pan ignored
+    Mono,
+}
+
+// gain with which the channels are summed when folding to mono
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonoFold {
+    // half of the sum, which keeps material that is the same on both sides at its level
+    Average,
+    // the sum over the square root of 2, which keeps uncorrelated material at its power
+    EqualPower,
+}
+
+// parameter a learned controller is routed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CcDestination {
+    Off,
+    Brightness,
+    Gain,
+    Cutoff,
+    Resonance,
+    Pan,
+}
+
+// which voice is sacrificed when a note-on exceeds the polyphony limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StealPriority {
+    Oldest,
+    // lowest current envelope level
+    Quietest,
+    // closest to the new note's pitch
+    Nearest,
+}
+
+// which partials the discrete summation sums over: the one-sided sum has partials at
+// u + k * v for k >= 0, the two-sided adds the mirrored partials at u - k * v, which with
+// u = v fall below the fundamental and fold back with inverted sign
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DsfVariant {
+    OneSided,
+    TwoSided,
+}
+
+// crossfade from the variant in use before a switch to the new one, so that switching
+// while notes sound does not click
+#[derive(Debug, Clone, Copy)]
+pub struct VariantFade {
+    from: DsfVariant,
+    to: DsfVariant,
+    progress: f32,
 }
 
 #[uri("https://github.com/Ninja-Koala/dsf-synth")]
@@ -53,16 +578,339 @@ pub struct Dsfsynth {
     gain: f32,
     base_note: u8,
     input_channel: Channel,
+    portamento_time: f32,
+    portamento_mode: PortamentoMode,
+    glide_direction: GlideDirection,
+    // most recently triggered voice and the pitch it was heading to
+    last_voice: Option<(u64, f32)>,
+    // pitch of the voice that was triggered or released last
+    last_pitch: Option<f32>,
+    min_note: u8,
+    max_note: u8,
+    fold_notes: bool,
+    left_trim: f32,
+    right_trim: f32,
+    left_gain_smoother: Smoother,
+    right_gain_smoother: Smoother,
+    reset_phase_on_trigger: bool,
+    // starts each voice, its right channel and its unison copies at the phase a free running
+    // oscillator at its pitch would have, so that voices meeting in the mono sum add up
+    phase_align: bool,
+    // free-running phase new voices start from when they don't reset their phase
+    master_phase: f32,
+    offline_quality: OfflineQuality,
+    oversampling: u32,
+    pan: f32,
+    pan_law: PanLaw,
+    amp_keytrack: f32,
+    humanize: f32,
+    stereo_detune: f32,
+    dsf_variant: DsfVariant,
+    variant_fade: VariantFade,
+    filter_cutoff: f32,
+    filter_resonance: f32,
+    filter_keytrack: f32,
+    invert_left: bool,
+    invert_right: bool,
+    env_mode: EnvelopeMode,
+    tuning_hz: f32,
+    // frequency of every midi note in Hz, replacing the tuning formula while pitch_table_set;
+    // hosts load it with midi tuning standard messages and keep it in the plugin state. it is
+    // allocated once at instantiation, so that loading it in run only overwrites entries
+    pitch_table: Box<[f32; 128]>,
+    pitch_table_set: bool,
+    tuner_tone: bool,
+    tuner_phase: f32,
+    // outputs the bare oscillators of the sounding voices, without envelope, velocity, gain
+    // or anything after them, to inspect their spectrum
+    raw_osc: bool,
+    // mod wheel from 0 to 1, opening the brightness up towards full, and expression from 0 to 1,
+    // reported as turning the gain down over EXPRESSION_RANGE but not applied to the audio
+    mod_wheel: f32,
+    expression: f32,
+    // latest pitch bend from -1 to 1 and the bend in semitones smoothed towards it
+    pitch_bend: f32,
+    bend_up_range: f32,
+    bend_down_range: f32,
+    pitch_bend_smoother: Smoother,
+    delay: Delay,
+    delay_time: f32,
+    delay_feedback: f32,
+    delay_mix: f32,
+    ping_pong: bool,
+    // vibrato lfo
+    lfo_rate: f32,
+    lfo_depth: f32,
+    lfo_phase: f32,
+    lfo_shape: LfoShape,
+    // value the sample and hold lfo holds for the current cycle, and the generator it is drawn
+    // from
+    lfo_held: f32,
+    lfo_rng: Rng,
+    // channel aftertouch from 0 to 1 and the vibrato depth it adds at full pressure
+    channel_pressure: f32,
+    aftertouch_to_vibrato: f32,
+    gate: Gate,
+    gate_threshold: f32,
+    gate_release: f32,
+    // envelope of the external input in seconds of attack and release, and how far it turns
+    // the synth down at full level, ducking it under the input
+    follower: EnvelopeFollower,
+    follower_attack: f32,
+    follower_release: f32,
+    follower_duck: f32,
+    click: f32,
+    // registered parameter selected by CC 101/100, its data entry value, and the master
+    // tuning it has set in cents and semitones
+    rpn: (u8, u8),
+    rpn_data: u16,
+    fine_tuning: f32,
+    coarse_tuning: f32,
+    // consecutive frames in which neither the output nor the delay line carried signal
+    quiet_frames: usize,
+    reverb: Reverb,
+    reverb_size: f32,
+    reverb_damp: f32,
+    reverb_mix: f32,
+    decorrelator: Decorrelator,
+    decorrelation: f32,
+    // single voice with low note priority that glides between overlapping notes instead
+    // of retriggering, and the keys held down while it is enabled with their channels and
+    // the notes the note range mapped them to
+    classic_mono: bool,
+    mono_notes: Vec<(wmidi::Channel, wmidi::Note, wmidi::Note)>,
+    clip_indicator: ClipIndicator,
+    // rate per second at which a voice's brightness falls exponentially as it rings
+    brightness_decay: f32,
+    // scales the oscillator against its brightness so the level stays put while sweeping it
+    brightness_comp: bool,
+    // follows the brightness per sample, so automating it does not step the timbre of held
+    // notes
+    brightness_smoother: Smoother,
+    // fastest change of the brightness per second, and the brightness it lets through
+    brightness_slew: f32,
+    slewed_brightness: f32,
+    // controller routed to a parameter, and the last value received on it; while set, that
+    // value overrides the parameter's port
+    cc_mapping: (u8, CcDestination),
+    mapped_cc_value: Option<u8>,
+    tilt: f32,
+    tilt_filter: Tilt,
+    glide_bend: GlideBend,
+    // seconds between the onsets of the notes of a chord, and the frame and count of the
+    // note-ons that have arrived together so far
+    strum: f32,
+    strum_frame: Option<u64>,
+    strum_index: u32,
+    // length in seconds of the fixed fade-in at every voice's onset, independent of the attack
+    click_smooth: f32,
+    // offset in semitones every voice starts at, and the time in seconds over which it sweeps
+    // to the note's pitch
+    pitch_env_amount: f32,
+    pitch_env_time: f32,
+    // level at which the external audio input joins the voices ahead of the effects
+    input_mix: f32,
+    // whether voices in their release take up one of the max_voices slots
+    release_counts: bool,
+    // fades the whole output out and back in while the voices keep running
+    mute: bool,
+    mute_smoother: Smoother,
+    // set by activate until the next render has settled the smoothers at their targets
+    settle_pending: bool,
+    // slow average of each output channel, checked by debug builds
+    #[cfg(debug_assertions)]
+    output_dc: (f32, f32),
+    // host tempo, and whether the lfo runs at a note division of it instead of lfo_rate
+    host_bpm: f32,
+    lfo_sync: bool,
+    lfo_division: LfoDivision,
+    // receives on every channel instead of only input_channel
+    omni: bool,
+    // level trims in dB for the voices of the first channels
+    channel_gains: [f32; 4],
+    lfo_retrigger: bool,
+    // highpass on the output against subsonic energy from low notes
+    hpf_cutoff: f32,
+    highpass: Highpass,
+    // note-ons beyond this many in one block are dropped, bounding the work a burst of
+    // midi can cause
+    max_noteons_per_block: u32,
+    block_noteons: u32,
+    // lowest velocity ratio a note plays at; incoming velocities are scaled onto the range
+    // up from it
+    velocity_floor: f32,
+    // velocity curve at note 0 and note 127, interpolated across the keyboard in between;
+    // positive values lift soft velocities, negative ones lower them
+    low_velocity_boost: f32,
+    high_velocity_boost: f32,
+    // largest random deviation of a note's velocity ratio, so repeated velocities vary
+    velocity_humanize: f32,
+    // note ranges of the enabled choke groups; a note in a group cuts off the other voices
+    // of its group, like a closed hi-hat cutting off an open one
+    choke_groups: [Option<(u8, u8)>; 2],
+    soft_clip: bool,
+    knee: f32,
+    clip_symmetry: f32,
+    metronome_enabled: bool,
+    host_speed: f32,
+    arp_enabled: bool,
+    // arpeggiator steps per beat, and the fraction of a step each of its notes sounds for
+    arp_rate: f32,
+    gate_length: f32,
+    arpeggiator: Arpeggiator,
+    metronome: Metronome,
+    // level of the key-off noise burst played on note-off, and its current decaying level
+    release_layer: f32,
+    release_noise: f32,
+    freeze: bool,
+    // position of the damper pedal from 0 to 1, and whether it damps continuously instead of
+    // switching at the middle
+    sustain_pedal: f32,
+    half_pedal: bool,
+    // keeps the voice of the last key let go of sounding until the next note, and that voice
+    hold_last: bool,
+    held_voice: Option<u64>,
+    // equal power gains of the front and the rear pair at the end of the previous block,
+    // ramped from towards those of the front_back position over the next one
+    #[cfg(feature = "surround")]
+    front_rear_gains: (f32, f32),
+    // levels and pan positions of the per-voice sub-oscillator and noise layers, which
+    // follow the voice's envelope next to the shepard tone
+    sub_level: f32,
+    sub_pan: f32,
+    noise_level: f32,
+    noise_pan: f32,
+    // glides whenever another note is held and never otherwise, whatever portamento_mode says
+    auto_glide: bool,
+    // moves the target of a glide by whole octaves to the one nearest the pitch it glides from
+    glide_octave_snap: bool,
+    // how far velocity raises or lowers the sustain level; a medium velocity leaves it as set
+    velocity_to_sustain: f32,
+    // how far velocity moves a voice from the centre, soft notes to the left and loud ones to
+    // the right for positive amounts
+    velocity_to_pan: f32,
+    // how far velocity shortens the glide into a note, loud notes gliding faster for positive
+    // amounts; a medium velocity leaves the portamento time as set
+    velocity_to_glide: f32,
+    // largest delay in ms between the channels of a voice, reached at the ends of the keyboard
+    haas: f32,
+    // lowers the polyphony while the estimated cost of a voice would take the synth past the
+    // budget, in oscillator evaluations per sample
+    cpu_throttle: bool,
+    cpu_budget: f32,
+    // number of detuned copies each note plays, their detune range in cents either side of
+    // the note, and how they are spread over it
+    unison: usize,
+    unison_detune: f32,
+    detune_distribution: DetuneDistribution,
+    // fraction of a cycle the copies' starting phases are spread over, 0 starting them all
+    // in phase
+    unison_phase_spread: f32,
+    output_mode: OutputMode,
+    mono_fold: MonoFold,
+    // lets the filter run past its resonance limit into a sine at the cutoff
+    self_osc: bool,
+    rng: Rng,
+    #[cfg(feature = "debug-automation")]
+    automation: AutomationLog,
     urids: URIDs,
     samplerate: f32,
-    active_tones: HashMap<u8, Tone>,
-    current_frame: u32,
+    // voices keyed by a monotonically increasing voice id
+    active_tones: HashMap<u64, Tone>,
+    // ids of the voices of each key, oldest first; keyed by the note number received rather
+    // than the mapped note, so that a note-off finds its voice however the note range changed
+    note_voices: HashMap<u8, Vec<u64>>,
+    // finished voices whose buffers are reused by new ones, so that playing does not allocate
+    // and free them on the audio thread for every note
+    tone_pool: Vec<Tone>,
+    // voices that finished during the current frame, reused from frame to frame
+    finished_tones: Vec<u64>,
+    // midi events of the current block at their frames, reused from block to block; only a
+    // sysex message allocates, to keep its data past the atom it was read from
+    events: Vec<(usize, MidiMessage<'static>)>,
+    next_voice_id: u64,
+    stack_same_note: bool,
+    max_voices: usize,
+    steal_priority: StealPriority,
+    current_frame: u64,
 }
 
+// largest w the sums are evaluated at; at w = 1 both divide 0 by 0 whenever v is a multiple
+// of a full turn, which a brightness of 127 reaches at every phase of 0
+const DSF_MAX_W: f32 = 0.995;
+
 fn dsf_inf(w: f32, u: f32, v: f32) -> f32 {
+    let w = w.min(DSF_MAX_W);
     (u.sin() - w * (u - v).sin()) / (1f32 + w * w - 2f32 * w * v.cos())
 }
 
+fn dsf_inf_two_sided(w: f32, u: f32, v: f32) -> f32 {
+    let w = w.min(DSF_MAX_W);
+    u.sin() * (1f32 - w * w) / (1f32 + w * w - 2f32 * w * v.cos())
+}
+
+fn dsf(variant: DsfVariant, w: f32, u: f32, v: f32) -> f32 {
+    match variant {
+        DsfVariant::OneSided => dsf_inf(w, u, v),
+        DsfVariant::TwoSided => dsf_inf_two_sided(w, u, v),
+    }
+}
+
+// release lengthening of a damper pedal pressed almost all the way with half pedaling
+const HALF_PEDAL_MAX_RELEASE_SCALE: f32 = 16f32;
+
+// shortening of the glide into a note at full velocity with the full velocity to glide amount;
+// the softest notes glide that much longer
+const VELOCITY_GLIDE_MAX_SCALE: f32 = 4f32;
+
+// as fast as the smoother moves on a jump across the whole range, so by default the limit
+// never holds back a change
+const BRIGHTNESS_DEFAULT_SLEW: f32 = 1f32 / SMOOTHING_TIME;
+
+// time over which a switch of the dsf variant crossfades
+const VARIANT_FADE_TIME: f32 = 0.005;
+
+impl VariantFade {
+    fn new(variant: DsfVariant) -> Self {
+        Self {
+            from: variant,
+            to: variant,
+            progress: 1f32,
+        }
+    }
+
+    // evaluates `f` for the variants being faded between, weighted by the fade
+    fn blend(&self, f: impl Fn(DsfVariant) -> f32) -> f32 {
+        if self.progress >= 1f32 {
+            f(self.to)
+        } else {
+            f(self.from) * (1f32 - self.progress) + f(self.to) * self.progress
+        }
+    }
+
+    fn dsf(&self, w: f32, u: f32, v: f32) -> f32 {
+        self.blend(|variant| dsf(variant, w, u, v))
+    }
+
+    fn advance(&mut self, step: f32) {
+        self.progress = (self.progress + step).min(1f32);
+    }
+}
+
+// upper limit of the compensation gain, reached as the two-sided sum thins out towards w = 1
+const BRIGHTNESS_COMP_MAX_GAIN: f32 = 12f32;
+
+// gain that brings the oscillator back to the rms level of a plain sine; the one-sided sum
+// peaks at 1 / (1 - w^2) and its rms grows with the square root of that, while the
+// two-sided sum keeps a peak of 1 and its rms falls by the same square root
+fn brightness_compensation(variant: DsfVariant, w: f32) -> f32 {
+    let root = (1f32 - w * w).max(0f32).sqrt();
+    match variant {
+        DsfVariant::OneSided => root,
+        DsfVariant::TwoSided => (1f32 / root).min(decibel(BRIGHTNESS_COMP_MAX_GAIN)),
+    }
+}
+
 fn midi_val_to_time(val: f32) -> f32 {
     (val / 8f32 - 10f32).exp()
 }
@@ -71,12 +919,204 @@ fn midi_val_to_ratio(val: f32) -> f32 {
     val / 127f32
 }
 
-fn midi_vals_to_adsr(attack: f32, decay: f32, sustain: f32, release: f32) -> Adsr {
+// maps 0..127 exponentially onto min_time..max_time; the default span gives the same
+// times as midi_val_to_time
+fn midi_val_to_time_span(val: f32, min_time: f32, max_time: f32) -> f32 {
+    let (min, max) = (min_time.ln(), max_time.ln());
+    (min + (max - min) * val / 127f32).exp()
+}
+
+fn midi_vals_to_adsr(
+    attack: f32,
+    decay: f32,
+    sustain: f32,
+    release: f32,
+    min_time: f32,
+    max_time: f32,
+) -> Adsr {
     Adsr {
-        attack: midi_val_to_time(attack),
-        decay: midi_val_to_time(decay),
+        attack: midi_val_to_time_span(attack, min_time, max_time),
+        decay: midi_val_to_time_span(decay, min_time, max_time),
         sustain: midi_val_to_ratio(sustain),
-        release: midi_val_to_time(release),
+        release: midi_val_to_time_span(release, min_time, max_time),
+        decay_curve: 0f32,
+        release_curve: 0f32,
+        end_level: 0f32,
+    }
+}
+
+// maps progress through a segment from 0..1 onto 0..1, falling quickly at first for
+// positive amounts
+fn curve(progress: f32, amount: f32) -> f32 {
+    let steepness = amount * CURVE_STEEPNESS;
+    (1f32 - (-steepness * progress).exp()) / (1f32 - (-steepness).exp())
+}
+
+fn port_to_portamento_mode(val: f32) -> PortamentoMode {
+    match val as u8 {
+        1 => PortamentoMode::Fingered,
+        2 => PortamentoMode::Free,
+        3 => PortamentoMode::Cascade,
+        _ => PortamentoMode::Off,
+    }
+}
+
+fn port_to_glide_direction(val: f32) -> GlideDirection {
+    match val as u8 {
+        1 => GlideDirection::Up,
+        2 => GlideDirection::Down,
+        _ => GlideDirection::Both,
+    }
+}
+
+// transposes a note by octaves until it lies within the range, if the range allows that
+fn fold_note(note: u8, min_note: u8, max_note: u8) -> Option<u8> {
+    let mut note = note as i32;
+    while note < min_note as i32 {
+        note += 12;
+    }
+    while note > max_note as i32 {
+        note -= 12;
+    }
+    if note >= min_note as i32 {
+        Some(note as u8)
+    } else {
+        None
+    }
+}
+
+fn port_to_offline_quality(val: f32) -> OfflineQuality {
+    match val as u8 {
+        1 => OfflineQuality::FreeWheeling,
+        2 => OfflineQuality::Always,
+        _ => OfflineQuality::Off,
+    }
+}
+
+fn port_to_pan_law(val: f32) -> PanLaw {
+    match val as u8 {
+        0 => PanLaw::ZeroDb,
+        2 => PanLaw::Minus4_5Db,
+        3 => PanLaw::Minus6Db,
+        _ => PanLaw::Minus3Db,
+    }
+}
+
+fn port_to_dsf_variant(val: f32) -> DsfVariant {
+    match val as u8 {
+        1 => DsfVariant::TwoSided,
+        _ => DsfVariant::OneSided,
+    }
+}
+
+fn port_to_envelope_mode(val: f32) -> EnvelopeMode {
+    match val as u8 {
+        1 => EnvelopeMode::Trigger,
+        _ => EnvelopeMode::Gate,
+    }
+}
+
+fn midi_pitch_bend_to_ratio(bend: wmidi::PitchBend) -> f32 {
+    (u16::from(bend) as f32 - 8192f32) / 8192f32
+}
+
+fn port_to_steal_priority(val: f32) -> StealPriority {
+    match val as u8 {
+        1 => StealPriority::Quietest,
+        2 => StealPriority::Nearest,
+        _ => StealPriority::Oldest,
+    }
+}
+
+fn port_to_glide_bend(val: f32) -> GlideBend {
+    match val as u8 {
+        1 => GlideBend::FromUnbent,
+        _ => GlideBend::FromBent,
+    }
+}
+
+fn port_to_lfo_division(val: f32) -> LfoDivision {
+    match val as u8 {
+        0 => LfoDivision::Whole,
+        1 => LfoDivision::Half,
+        3 => LfoDivision::Eighth,
+        4 => LfoDivision::EighthTriplet,
+        5 => LfoDivision::Sixteenth,
+        _ => LfoDivision::Quarter,
+    }
+}
+
+// length of a division in beats, a beat being a quarter note
+fn lfo_division_beats(division: LfoDivision) -> f32 {
+    match division {
+        LfoDivision::Whole => 4f32,
+        LfoDivision::Half => 2f32,
+        LfoDivision::Quarter => 1f32,
+        LfoDivision::Eighth => 0.5,
+        LfoDivision::EighthTriplet => 1f32 / 3f32,
+        LfoDivision::Sixteenth => 0.25,
+    }
+}
+
+fn port_to_lfo_shape(val: f32) -> LfoShape {
+    match val as u8 {
+        1 => LfoShape::Triangle,
+        2 => LfoShape::SampleAndHold,
+        _ => LfoShape::Sine,
+    }
+}
+
+// lfo output from -1 to 1 at `phase`; the triangle rises through 0 at phase 0 like the sine
+fn lfo_value(shape: LfoShape, phase: f32, held: f32) -> f32 {
+    match shape {
+        LfoShape::Sine => phase.sin(),
+        LfoShape::Triangle => {
+            let position = (phase / std::f32::consts::TAU + 0.25).fract();
+            1f32 - 4f32 * (position - 0.5).abs()
+        }
+        LfoShape::SampleAndHold => held,
+    }
+}
+
+// moves an lfo phase on by `increment`, drawing the next held value for each cycle it completes
+fn advance_lfo(phase: &mut f32, held: &mut f32, increment: f32, rng: &mut Rng) {
+    let advanced = *phase + increment;
+    for _ in 0..(advanced / std::f32::consts::TAU) as u32 {
+        *held = rng.next_bipolar();
+    }
+    *phase = advanced.rem_euclid(std::f32::consts::TAU);
+}
+
+fn port_to_detune_distribution(val: f32) -> DetuneDistribution {
+    match val as u8 {
+        1 => DetuneDistribution::Random,
+        2 => DetuneDistribution::Gaussian,
+        _ => DetuneDistribution::Even,
+    }
+}
+
+fn port_to_output_mode(val: f32) -> OutputMode {
+    match val as u8 {
+        1 => OutputMode::Mono,
+        _ => OutputMode::Stereo,
+    }
+}
+
+fn port_to_mono_fold(val: f32) -> MonoFold {
+    match val as u8 {
+        1 => MonoFold::EqualPower,
+        _ => MonoFold::Average,
+    }
+}
+
+fn port_to_cc_destination(val: f32) -> CcDestination {
+    match val as u8 {
+        1 => CcDestination::Brightness,
+        2 => CcDestination::Gain,
+        3 => CcDestination::Cutoff,
+        4 => CcDestination::Resonance,
+        5 => CcDestination::Pan,
+        _ => CcDestination::Off,
     }
 }
 
@@ -86,161 +1126,2763 @@ fn decibel(val: f32) -> f32 {
 
 fn ads(adsr: &Adsr, time: f32) -> f32 {
     if time < adsr.attack {
-        return time / adsr.attack;
+        time / adsr.attack
     } else {
         let decay_time = adsr.decay * (1f32 - adsr.sustain);
         if time < adsr.attack + decay_time {
-            return 1f32 - (time - adsr.attack) / adsr.decay;
+            if adsr.decay_curve == 0f32 {
+                1f32 - (time - adsr.attack) / adsr.decay
+            } else {
+                let progress = (time - adsr.attack) / decay_time;
+                1f32 - (1f32 - adsr.sustain) * curve(progress, adsr.decay_curve)
+            }
+        } else {
+            adsr.sustain
+        }
+    }
+}
+
+fn ad(adsr: &Adsr, time: f32) -> Option<f32> {
+    if time < adsr.attack {
+        Some(time / adsr.attack)
+    } else if time < adsr.attack + adsr.decay {
+        let progress = (time - adsr.attack) / adsr.decay;
+        if adsr.decay_curve == 0f32 {
+            Some(1f32 - progress)
         } else {
-            return adsr.sustain;
+            Some(1f32 - curve(progress, adsr.decay_curve))
         }
+    } else {
+        None
     }
 }
 
-fn envelope(tone: &Tone, frame_index: u32, adsr: &Adsr, samplerate: f32) -> Option<f32> {
-    if let Some(released) = tone.time_released {
+fn envelope(tone: &Tone, frame_index: u64, adsr: &Adsr, samplerate: f32) -> Option<f32> {
+    // a strummed voice that has not started yet
+    if frame_index < tone.time_pressed {
+        return Some(0f32);
+    }
+    let adsr = &Adsr {
+        attack: adsr.attack * tone.attack_scale,
+        release: adsr.release * tone.release_scale,
+        sustain: (adsr.sustain * tone.sustain_scale).clamp(0f32, 1f32),
+        ..adsr.clone()
+    };
+    let start = tone.envelope_start();
+    if tone.env_mode == EnvelopeMode::Trigger {
+        let time = ((frame_index - start) as f32) / samplerate;
+        ad(adsr, time)
+    } else if let Some(released) = tone.time_released {
         let time = ((frame_index - released) as f32) / samplerate;
-        let time_at_release = ((released - tone.time_pressed) as f32) / samplerate;
+        let time_at_release = ((released - start) as f32) / samplerate;
         let val_at_release = ads(adsr, time_at_release);
+        // the release takes as long to fall from the level it started at as the linear
+        // one, and ends early once it drops below the threshold
         let release_time = adsr.release * val_at_release;
-        if time < release_time {
-            return Some(val_at_release - time / adsr.release);
+        if time >= release_time {
+            None
+        } else if adsr.release_curve == 0f32 {
+            Some(val_at_release - time / adsr.release)
         } else {
-            return None;
+            let progress = time / release_time;
+            Some(val_at_release * (1f32 - curve(progress, adsr.release_curve)))
         }
+        .filter(|&level| level >= adsr.end_level)
     } else {
-        let time = ((frame_index - tone.time_pressed) as f32) / samplerate;
-        return Some(ads(adsr, time));
+        let time = ((frame_index - start) as f32) / samplerate;
+        Some(ads(adsr, time))
+    }
+}
+
+// orders each chord, i.e. each run of note-ons on the same frame, from the lowest note up,
+// so that a strum sweeps across it
+fn sort_chords(events: &mut [(usize, MidiMessage)]) {
+    let mut start = 0usize;
+    while start < events.len() {
+        let mut end = start;
+        while end < events.len()
+            && events[end].0 == events[start].0
+            && matches!(events[end].1, MidiMessage::NoteOn(..))
+        {
+            end += 1;
+        }
+        events[start..end].sort_by_key(|(_, message)| match message {
+            MidiMessage::NoteOn(_, note, _) => u8::from(*note),
+            _ => 0u8,
+        });
+        start = end.max(start + 1);
     }
 }
 
-fn shepard_tone(brightness: f32, phase: f32, base_note: u8, note: u8) -> f32 {
-    let index = (note-base_note).rem_euclid(12u8);
+fn shepard_tone(brightness: f32, phase: f32, base_note: u8, note: u8, variant: VariantFade) -> f32 {
+    let index = (note as i32 - base_note as i32).rem_euclid(12);
+    let brightness = brightness.min(DSF_MAX_W);
     let t = index as f32 / 12f32;
 
-    brightness.powf(t) * dsf_inf(brightness.powf(1f32 + t), phase, phase)
-    + (t.exp2() - 1f32) * dsf_inf(brightness * brightness, phase / 2f32, phase)
+    brightness.powf(t) * variant.dsf(brightness.powf(1f32 + t), phase, phase)
+    + (t.exp2() - 1f32) * variant.dsf(brightness * brightness, phase / 2f32, phase)
 }
 
-impl Dsfsynth {
-    fn midi_note_to_phase_increment(&self, note: wmidi::Note) -> f32 {
-        let index = self.base_note as i32 + ((u8::from(note) as i32) - (self.base_note as i32)).rem_euclid(12i32);
-        let pitch = ((index as f32 - 69f32) / 12f32).exp2() * 440f32;
-        std::f32::consts::TAU * pitch / self.samplerate
+const SMOOTHING_TIME: f32 = 0.01;
+const MUTE_TIME: f32 = 0.005;
+const MASTER_PHASE_PITCH: f32 = 440f32;
+// note at which keytracking leaves level and cutoff unchanged
+const KEYTRACK_CENTER: u8 = 60;
+
+// oversampling factor used instead of 1 when rendering in offline quality
+const OFFLINE_OVERSAMPLING: u32 = 8;
+
+// sysex ids of the midi tuning standard messages understood
+const MTS_REALTIME: u8 = 0x7f;
+const MTS_NON_REALTIME: u8 = 0x7e;
+const MTS_ID: u8 = 0x08;
+const MTS_BULK_DUMP: u8 = 0x01;
+const MTS_SINGLE_NOTE: u8 = 0x02;
+const MTS_NAME_LENGTH: usize = 16;
+// the tuning standard gives frequencies against this a4 whatever the tuning port says
+const MTS_A4_FREQUENCY: f32 = 440f32;
+
+// attenuation in dB of the expression controller turned all the way down
+const EXPRESSION_RANGE: f32 = 50f32;
+
+// midi events a block holds without allocating
+const EVENT_CAPACITY: usize = 1024;
+
+const RNG_SEED: u32 = 0x2545_f491;
+// the sample and hold lfo draws from its own generator, so that its shape does not change
+// the random values of the other features
+const LFO_RNG_SEED: u32 = 0x9e37_79b9;
+const RPN_NULL: (u8, u8) = (127, 127);
+const RPN_FINE_TUNING: (u8, u8) = (0, 1);
+const RPN_COARSE_TUNING: (u8, u8) = (0, 2);
+// steepness of an envelope segment at a curve amount of 1
+const CURVE_STEEPNESS: f32 = 8f32;
+// at this cutoff the filter is bypassed entirely
+const FILTER_MAX_CUTOFF: f32 = 20000f32;
+
+// level a self-oscillating filter is started at, since without damping it holds whatever
+// it starts from and would stay silent when started from rest
+const SELF_OSC_LEVEL: f32 = 0.25;
+// knee of the clipper on the states that keeps a driven self-oscillation bounded; below
+// 1 - knee the states pass untouched, so an undriven tone neither grows nor fades
+const SELF_OSC_KNEE: f32 = 0.5;
+
+fn svf_coefficients(
+    cutoff: f32,
+    resonance: f32,
+    self_osc: bool,
+    samplerate: f32,
+) -> SvfCoefficients {
+    let cutoff = cutoff.min(0.45 * samplerate);
+    let g = (std::f32::consts::PI * cutoff / samplerate).tan();
+    let resonance = resonance.clamp(0f32, 1f32);
+    let (k, input_gain) = if self_osc {
+        // damping from 2 down to 0, where the filter rings forever at its cutoff, with the
+        // input scaled down along with it so the resonance peak does not grow as 1 / k
+        let k = 2f32 - 2f32 * resonance;
+        (k, k.max(0.1) / 2f32)
+    } else {
+        // damping from 2 (no resonance peak) down to almost self-oscillation
+        (2f32 - 1.98 * resonance, 1f32)
+    };
+    let a1 = 1f32 / (1f32 + g * (g + k));
+    let a2 = g * a1;
+    SvfCoefficients {
+        a1,
+        a2,
+        a3: g * a2,
+        input_gain,
+        saturate: self_osc,
     }
 }
 
-impl Plugin for Dsfsynth {
-    type Ports = Ports;
+impl Svf {
+    fn lowpass(&mut self, coefficients: &SvfCoefficients, input: f32) -> f32 {
+        let v3 = input * coefficients.input_gain - self.ic2eq;
+        let v1 = coefficients.a1 * self.ic1eq + coefficients.a2 * v3;
+        let v2 = self.ic2eq + coefficients.a2 * self.ic1eq + coefficients.a3 * v3;
+        self.ic1eq = 2f32 * v1 - self.ic1eq;
+        self.ic2eq = 2f32 * v2 - self.ic2eq;
+        if coefficients.saturate {
+            self.ic1eq = soft_knee_clip(self.ic1eq, SELF_OSC_KNEE);
+            self.ic2eq = soft_knee_clip(self.ic2eq, SELF_OSC_KNEE);
+        }
+        v2
+    }
 
-    type InitFeatures = Features<'static>;
-    type AudioFeatures = ();
+    // starts a self-oscillation that would otherwise stay at rest
+    fn kick(&mut self) {
+        if self.ic1eq.abs() + self.ic2eq.abs() < SELF_OSC_LEVEL {
+            self.ic1eq = SELF_OSC_LEVEL;
+            self.ic2eq = 0f32;
+        }
+    }
+}
 
-    fn new(plugin_info: &PluginInfo, features: &mut Features<'static>) -> Option<Self> {
-        Some(Self {
-            adsr: Adsr {
-                attack: -6f32.exp(),
-                decay: -6f32.exp(),
-                sustain: 64f32 / 127f32,
-                release: -6f32.exp(),
-            },
-            brightness: 64f32 / 127f32,
-            gain: -20f32,
-            base_note: 69u8,
-            input_channel: Channel::Ch1,
-            urids: features.map.populate_collection()?,
-            samplerate: plugin_info.sample_rate() as f32,
-            active_tones: HashMap::new(),
-            current_frame: 0u32,
-        })
+impl Rng {
+    fn new(seed: u32) -> Self {
+        Self { state: seed }
     }
 
-    fn run(&mut self, ports: &mut Ports, _: &mut (), sample_count: u32) {
-        self.adsr = midi_vals_to_adsr(
-            *(ports.attack),
-            *(ports.decay),
-            *(ports.sustain),
-            *(ports.release),
-        );
-        self.brightness = midi_val_to_ratio(*(ports.brightness));
-        self.gain = *(ports.gain);
-        self.base_note = *(ports.base_note) as u8;
-        self.input_channel =
-            wmidi::Channel::from_index(*(ports.input_channel) as u8 - 1u8).unwrap();
+    fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
 
-        let input_sequence = ports
-            .midi_input
-            .read(self.urids.atom.sequence, self.urids.unit.beat)
-            .unwrap();
+    // uniformly distributed in [0, 1)
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
 
-        for (_, atom) in input_sequence {
-            let message = if let Some(message) = atom.read(self.urids.midi.wmidi, ()) {
-                message
-            } else {
-                continue;
+    // uniformly distributed in [-1, 1)
+    fn next_bipolar(&mut self) -> f32 {
+        2f32 * self.next_f32() - 1f32
+    }
+}
+
+// longest delay time the delay buffers are sized for, in seconds
+const MAX_DELAY_TIME: f32 = 2f32;
+
+impl Delay {
+    fn new(samplerate: f32) -> Self {
+        let length = (MAX_DELAY_TIME * samplerate) as usize + 1;
+        Self {
+            left: vec![0f32; length],
+            right: vec![0f32; length],
+            position: 0usize,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.left.iter_mut().for_each(|sample| *sample = 0f32);
+        self.right.iter_mut().for_each(|sample| *sample = 0f32);
+        self.position = 0usize;
+    }
+
+    // returns the delayed signal; in ping pong mode the input enters the left line only and
+    // each line feeds back into the other, so echoes alternate between the channels
+    fn process(
+        &mut self,
+        left: f32,
+        right: f32,
+        delay_frames: usize,
+        feedback: f32,
+        ping_pong: bool,
+    ) -> (f32, f32) {
+        let length = self.left.len();
+        let read = (self.position + length - delay_frames.clamp(1, length - 1)) % length;
+        let (delayed_left, delayed_right) = (self.left[read], self.right[read]);
+        if ping_pong {
+            self.left[self.position] = 0.5 * (left + right) + feedback * delayed_right;
+            self.right[self.position] = feedback * delayed_left;
+        } else {
+            self.left[self.position] = left + feedback * delayed_left;
+            self.right[self.position] = right + feedback * delayed_right;
+        }
+        self.position = (self.position + 1) % length;
+        (delayed_left, delayed_right)
+    }
+}
+
+// freeverb's delay lengths in samples at 44.1 kHz
+const REVERB_COMB_LENGTHS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const REVERB_ALLPASS_LENGTHS: [usize; 4] = [556, 441, 341, 225];
+const REVERB_STEREO_SPREAD: usize = 23;
+const REVERB_INPUT_GAIN: f32 = 0.015;
+const REVERB_WET_GAIN: f32 = 3f32;
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0f32; length.max(1)],
+            position: 0usize,
+            filter_store: 0f32,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damp: f32) -> f32 {
+        let output = self.buffer[self.position];
+        self.filter_store = output * (1f32 - damp) + self.filter_store * damp;
+        self.buffer[self.position] = input + self.filter_store * feedback;
+        self.position = (self.position + 1) % self.buffer.len();
+        output
+    }
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0f32; length.max(1)],
+            position: 0usize,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.position];
+        self.buffer[self.position] = input + delayed * 0.5;
+        self.position = (self.position + 1) % self.buffer.len();
+        delayed - input
+    }
+
+    // a true allpass with the given coefficient, leaving every frequency at its level
+    fn process_with(&mut self, input: f32, coefficient: f32) -> f32 {
+        let delayed = self.buffer[self.position];
+        let output = delayed - coefficient * input;
+        self.buffer[self.position] = input + coefficient * output;
+        self.position = (self.position + 1) % self.buffer.len();
+        output
+    }
+}
+
+impl Reverb {
+    fn new(samplerate: f32) -> Self {
+        let scale = |length: usize| (length as f32 * samplerate / 44100f32) as usize;
+        Self {
+            left_combs: REVERB_COMB_LENGTHS
+                .iter()
+                .map(|&length| Comb::new(scale(length)))
+                .collect(),
+            right_combs: REVERB_COMB_LENGTHS
+                .iter()
+                .map(|&length| Comb::new(scale(length + REVERB_STEREO_SPREAD)))
+                .collect(),
+            left_allpasses: REVERB_ALLPASS_LENGTHS
+                .iter()
+                .map(|&length| Allpass::new(scale(length)))
+                .collect(),
+            right_allpasses: REVERB_ALLPASS_LENGTHS
+                .iter()
+                .map(|&length| Allpass::new(scale(length + REVERB_STEREO_SPREAD)))
+                .collect(),
+        }
+    }
+
+    fn clear(&mut self) {
+        for comb in self.left_combs.iter_mut().chain(self.right_combs.iter_mut()) {
+            comb.buffer.iter_mut().for_each(|sample| *sample = 0f32);
+            comb.filter_store = 0f32;
+        }
+        for allpass in self.left_allpasses.iter_mut().chain(self.right_allpasses.iter_mut()) {
+            allpass.buffer.iter_mut().for_each(|sample| *sample = 0f32);
+        }
+    }
+
+    // longest delay line, after which silence in means silence out
+    fn length(&self) -> usize {
+        self.right_combs.iter().map(|comb| comb.buffer.len()).max().unwrap_or(0)
+            + self.right_allpasses.iter().map(|allpass| allpass.buffer.len()).sum::<usize>()
+    }
+
+    // returns the wet signal for a room size and damping from 0 to 1
+    fn process(&mut self, left: f32, right: f32, size: f32, damp: f32) -> (f32, f32) {
+        let input = (left + right) * REVERB_INPUT_GAIN;
+        let feedback = 0.7 + 0.28 * size;
+        let damp = 0.4 * damp;
+        let mut left = self
+            .left_combs
+            .iter_mut()
+            .map(|comb| comb.process(input, feedback, damp))
+            .sum::<f32>();
+        let mut right = self
+            .right_combs
+            .iter_mut()
+            .map(|comb| comb.process(input, feedback, damp))
+            .sum::<f32>();
+        for allpass in self.left_allpasses.iter_mut() {
+            left = allpass.process(left);
+        }
+        for allpass in self.right_allpasses.iter_mut() {
+            right = allpass.process(right);
+        }
+        (left * REVERB_WET_GAIN, right * REVERB_WET_GAIN)
+    }
+}
+
+// delay lengths at 44.1kHz and coefficients of the decorrelation allpasses per channel
+const DECORRELATOR_LEFT: [(usize, f32); 3] = [(113, 0.6), (79, -0.5), (37, 0.4)];
+const DECORRELATOR_RIGHT: [(usize, f32); 3] = [(97, -0.6), (61, 0.5), (43, -0.4)];
+
+impl Decorrelator {
+    fn new(samplerate: f32) -> Self {
+        let allpasses = |stages: &[(usize, f32)]| {
+            stages
+                .iter()
+                .map(|&(length, _)| Allpass::new((length as f32 * samplerate / 44100f32) as usize))
+                .collect()
+        };
+        Self {
+            left_allpasses: allpasses(&DECORRELATOR_LEFT),
+            right_allpasses: allpasses(&DECORRELATOR_RIGHT),
+        }
+    }
+
+    fn clear(&mut self) {
+        for allpass in self.left_allpasses.iter_mut().chain(self.right_allpasses.iter_mut()) {
+            allpass.buffer.iter_mut().for_each(|sample| *sample = 0f32);
+        }
+    }
+
+    fn length(&self) -> usize {
+        let length = |allpasses: &[Allpass]| {
+            allpasses.iter().map(|allpass| allpass.buffer.len()).sum::<usize>()
+        };
+        length(&self.left_allpasses).max(length(&self.right_allpasses))
+    }
+
+    // widens by `amount` from 0 to 1, keeping the sum of the two channels as it was
+    fn process(&mut self, left: f32, right: f32, amount: f32) -> (f32, f32) {
+        let mid = (left + right) * 0.5;
+        let mut left_copy = mid;
+        for (allpass, &(_, coefficient)) in self.left_allpasses.iter_mut().zip(&DECORRELATOR_LEFT) {
+            left_copy = allpass.process_with(left_copy, coefficient);
+        }
+        let mut right_copy = mid;
+        for (allpass, &(_, coefficient)) in
+            self.right_allpasses.iter_mut().zip(&DECORRELATOR_RIGHT)
+        {
+            right_copy = allpass.process_with(right_copy, coefficient);
+        }
+        let side = (left_copy - right_copy) * 0.5 * amount;
+        (left + side, right - side)
+    }
+}
+
+// at or below this threshold in dB the gate is disabled
+const GATE_OFF_THRESHOLD: f32 = -96f32;
+const GATE_ATTACK_TIME: f32 = 0.001;
+const GATE_DETECTOR_TIME: f32 = 0.02;
+// length of the noise transient at the start of each voice, in seconds
+const CLICK_TIME: f32 = 0.002;
+// the tilt eq turns around this frequency, boosting one side and cutting the other by up
+// to this many dB
+const TILT_PIVOT: f32 = 1000f32;
+const TILT_MAX_GAIN: f32 = 6f32;
+// how long the clip indicator stays lit after the last clipped sample, and how long it
+// then takes to fall back to 0, in seconds
+const CLIP_HOLD_TIME: f32 = 1f32;
+const CLIP_FALL_TIME: f32 = 0.25;
+const DEFAULT_CLICK_SMOOTH: f32 = 0.0005;
+const METRONOME_PITCH: f32 = 1000f32;
+const METRONOME_DOWNBEAT_PITCH: f32 = 1500f32;
+const METRONOME_DECAY_TIME: f32 = 0.01;
+const METRONOME_LEVEL: f32 = 0.25;
+// release velocity assumed for note-offs that do not carry one
+const DEFAULT_RELEASE_VELOCITY: u8 = 64;
+// decay time constant of the key-off noise burst, in seconds
+const RELEASE_LAYER_TIME: f32 = 0.015;
+// time over which a choked voice fades out, in seconds
+const CHOKE_TIME: f32 = 0.005;
+// level below which output and effect tails count as silence
+const SILENCE_THRESHOLD: f32 = 1e-6;
+// time over which debug builds average the output for the dc check, and the average it must
+// stay under; a full scale offset held that long means a feature went wrong
+#[cfg(debug_assertions)]
+const DC_GUARD_TIME: f32 = 1f32;
+#[cfg(debug_assertions)]
+const DC_GUARD_LIMIT: f32 = 1f32;
+
+impl Tilt {
+    // tilts the spectrum towards the highs for positive and the lows for negative values;
+    // the low and high bands sum back to the input, so a tilt of 0 stays flat
+    fn process(
+        &mut self,
+        left: f32,
+        right: f32,
+        coefficient: f32,
+        low: f32,
+        high: f32,
+    ) -> (f32, f32) {
+        self.left += (left - self.left) * coefficient;
+        self.right += (right - self.right) * coefficient;
+        (
+            self.left * low + (left - self.left) * high,
+            self.right * low + (right - self.right) * high,
+        )
+    }
+}
+
+impl Highpass {
+    fn process(&mut self, left: f32, right: f32, coefficient: f32) -> (f32, f32) {
+        self.left_output = coefficient * (self.left_output + left - self.left_input);
+        self.right_output = coefficient * (self.right_output + right - self.right_input);
+        self.left_input = left;
+        self.right_input = right;
+        // a non-finite sample would otherwise stay in the feedback for good, so the state
+        // starts over and the samples after it come through again
+        if !self.left_output.is_finite() {
+            (self.left_input, self.left_output) = (0f32, 0f32);
+        }
+        if !self.right_output.is_finite() {
+            (self.right_input, self.right_output) = (0f32, 0f32);
+        }
+        (self.left_output, self.right_output)
+    }
+}
+
+impl Metronome {
+    // advances by `beat_increment` beats and returns the click sample, starting a new click
+    // whenever the position enters another beat
+    fn process(&mut self, beat_increment: f64, samplerate: f32) -> f32 {
+        let entered_beat = match self.last_position {
+            Some(last) => last.floor() != self.position.floor(),
+            None => self.position.fract() == 0f64,
+        };
+        if entered_beat {
+            let pitch = if self.position < 1f64 {
+                METRONOME_DOWNBEAT_PITCH
+            } else {
+                METRONOME_PITCH
             };
+            self.level = METRONOME_LEVEL;
+            self.phase = 0f32;
+            self.phase_increment = std::f32::consts::TAU * pitch / samplerate;
+        }
+        self.last_position = Some(self.position);
+        self.position += beat_increment;
+        let click = self.level * self.phase.sin();
+        self.level *= (-1f32 / (METRONOME_DECAY_TIME * samplerate)).exp();
+        self.phase = (self.phase + self.phase_increment).rem_euclid(std::f32::consts::TAU);
+        click
+    }
+}
 
-            match message {
-                MidiMessage::NoteOn(channel, note, velocity) => {
-                    if channel == self.input_channel {
-                        self.active_tones.insert(
-                            u8::from(note),
-                            Tone {
-                                phase_increment: self.midi_note_to_phase_increment(note),
-                                time_pressed: self.current_frame,
-                                time_released: None,
-                                velocity: midi_val_to_ratio(u8::from(velocity) as f32),
-                                phase: 0f32,
-                            },
-                        );
-                    }
+impl Arpeggiator {
+    // adds a key, starting the steps at `frame` if it is the only one held
+    fn press(
+        &mut self,
+        channel: wmidi::Channel,
+        note: wmidi::Note,
+        velocity: wmidi::Velocity,
+        frame: u64,
+    ) {
+        self.keys.retain(|&(held_channel, held, _)| (held_channel, held) != (channel, note));
+        let index = self.keys.partition_point(|&(_, held, _)| held <= note);
+        self.keys.insert(index, (channel, note, velocity));
+        self.next_step.get_or_insert(frame);
+    }
+
+    // removes a key, stopping the steps once none is held; the sounding note still gets its
+    // full gate length
+    fn release(&mut self, channel: wmidi::Channel, note: wmidi::Note) {
+        self.keys.retain(|&(held_channel, held, _)| (held_channel, held) != (channel, note));
+        if self.keys.is_empty() {
+            self.next_step = None;
+            self.position = 0usize;
+        }
+    }
+
+    // the frame of whichever comes first, the release of the sounding note or the next step
+    fn next_event(&self) -> Option<u64> {
+        let release = self.sounding.map(|(_, _, frame)| frame);
+        match (release, self.next_step) {
+            (Some(release), Some(step)) => Some(release.min(step)),
+            (release, step) => release.or(step),
+        }
+    }
+}
+
+impl ClipIndicator {
+    // advances the indicator by `frames`, relighting it if any of them clipped
+    fn process(&mut self, clipped: bool, frames: u32, samplerate: f32) {
+        if clipped {
+            self.level = 1f32;
+            self.hold_frames = (CLIP_HOLD_TIME * samplerate) as u32;
+        } else if self.hold_frames >= frames {
+            self.hold_frames -= frames;
+        } else {
+            let falling = (frames - self.hold_frames) as f32;
+            self.hold_frames = 0u32;
+            self.level = (self.level - falling / (CLIP_FALL_TIME * samplerate)).max(0f32);
+        }
+    }
+}
+
+impl Gate {
+    fn new(samplerate: f32) -> Self {
+        Self {
+            detector: 0f32,
+            detector_decay: (-1f32 / (GATE_DETECTOR_TIME * samplerate)).exp(),
+            attack_step: 1f32 / (GATE_ATTACK_TIME * samplerate),
+            gain: 1f32,
+        }
+    }
+
+    // returns the gain to apply, ramping linearly open within the attack time and closed
+    // by `release_step` per sample
+    fn process(&mut self, left: f32, right: f32, threshold: f32, release_step: f32) -> f32 {
+        let level = left.abs().max(right.abs());
+        self.detector = level.max(self.detector * self.detector_decay);
+        if self.detector >= threshold {
+            self.gain = (self.gain + self.attack_step).min(1f32);
+        } else {
+            self.gain = (self.gain - release_step).max(0f32);
+        }
+        self.gain
+    }
+}
+
+impl EnvelopeFollower {
+    // follows the peak of both channels, approaching it by `attack` per sample while it rises
+    // and by `release` while it falls
+    fn process(&mut self, left: f32, right: f32, attack: f32, release: f32) -> f32 {
+        let input = left.abs().max(right.abs());
+        let coefficient = if input > self.level { attack } else { release };
+        self.level += (input - self.level) * coefficient;
+        self.level
+    }
+}
+
+impl Smoother {
+    fn new(value: f32, time: f32, samplerate: f32) -> Self {
+        Self {
+            value,
+            coefficient: 1f32 - (-1f32 / (time * samplerate)).exp(),
+        }
+    }
+
+    fn next(&mut self, target: f32) -> f32 {
+        self.value += (target - self.value) * self.coefficient;
+        self.value
+    }
+}
+
+// averages the oscillator over `oversampling` points within one sample period, which
+// attenuates the partials of the infinite sum that would alias
+fn oversampled_shepard_tone(
+    brightness: f32,
+    phase: f32,
+    phase_increment: f32,
+    base_note: u8,
+    note: u8,
+    variant: VariantFade,
+    oversampling: u32,
+) -> f32 {
+    let step = phase_increment / oversampling as f32;
+    (0..oversampling)
+        .map(|index| {
+            let phase = (phase + step * index as f32).rem_euclid(std::f32::consts::TAU);
+            shepard_tone(brightness, phase, base_note, note, variant)
+        })
+        .sum::<f32>()
+        / oversampling as f32
+}
+
+// limits to +-1, linear up to 1 - knee and bending smoothly into the limit by 1 + knee;
+// a knee of 0 clips hard
+fn soft_knee_clip(val: f32, knee: f32) -> f32 {
+    let magnitude = val.abs();
+    let shaped = if magnitude <= 1f32 - knee {
+        magnitude
+    } else if magnitude >= 1f32 + knee {
+        1f32
+    } else {
+        let over = magnitude - (1f32 - knee);
+        magnitude - over * over / (4f32 * knee)
+    };
+    shaped.copysign(val)
+}
+
+// shifts the signal by `symmetry` ahead of the clipper so one half of the wave saturates
+// before the other, which adds even harmonics; the shift is taken out again afterwards
+fn saturate(val: f32, knee: f32, symmetry: f32) -> f32 {
+    soft_knee_clip(val + symmetry, knee) - soft_knee_clip(symmetry, knee)
+}
+
+// correlation coefficient between the channels from -1 to 1; values near 1 stay as loud
+// summed to mono, values below 0 cancel out. silence counts as fully correlated
+fn correlation(left: &[f32], right: &[f32]) -> f32 {
+    let (mut product, mut left_energy, mut right_energy) = (0f64, 0f64, 0f64);
+    for (&left, &right) in left.iter().zip(right.iter()) {
+        product += left as f64 * right as f64;
+        left_energy += left as f64 * left as f64;
+        right_energy += right as f64 * right as f64;
+    }
+    let energy = (left_energy * right_energy).sqrt();
+    if energy > 0f64 {
+        (product / energy) as f32
+    } else {
+        1f32
+    }
+}
+
+// the octave of `target` closest to `source`
+fn nearest_octave(target: f32, source: f32) -> f32 {
+    target * (source / target).log2().round().exp2()
+}
+
+// left and right gain for a pan position from -1 (left) to 1 (right)
+fn pan_gains(pan: f32, pan_law: PanLaw) -> (f32, f32) {
+    let position = (pan.clamp(-1f32, 1f32) + 1f32) / 2f32;
+    let angle = position * std::f32::consts::FRAC_PI_2;
+    match pan_law {
+        PanLaw::ZeroDb => (
+            (2f32 - 2f32 * position).min(1f32),
+            (2f32 * position).min(1f32),
+        ),
+        PanLaw::Minus3Db => (angle.cos(), angle.sin()),
+        PanLaw::Minus4_5Db => (
+            ((1f32 - position) * angle.cos()).sqrt(),
+            (position * angle.sin()).sqrt(),
+        ),
+        PanLaw::Minus6Db => (1f32 - position, position),
+    }
+}
+
+// pan gains scaled so that the centre plays at unity, leaving a centred sound as loud as it
+// was before panning; the law then sets how much louder either side gets
+fn unity_pan_gains(pan: f32, pan_law: PanLaw) -> (f32, f32) {
+    let (centre, _) = pan_gains(0f32, pan_law);
+    let (left, right) = pan_gains(pan, pan_law);
+    (left / centre, right / centre)
+}
+
+impl Tone {
+    fn new(note: u8, phase_increment: f32, time_pressed: u64, velocity: f32) -> Self {
+        Self {
+            note,
+            key: note,
+            channel: wmidi::Channel::Ch1,
+            phase_increment,
+            target_phase_increment: phase_increment,
+            glide_ratio: 1f32,
+            glide_frames: 0u32,
+            time_pressed,
+            time_released: None,
+            velocity,
+            amplitude: 1f32,
+            env_mode: EnvelopeMode::Gate,
+            pressure: 0f32,
+            attack_scale: 1f32,
+            release_scale: 1f32,
+            sustain_scale: 1f32,
+            left_pan: 1f32,
+            right_pan: 1f32,
+            haas: None,
+            phase: 0f32,
+            right_phase: 0f32,
+            unison: Vec::new(),
+            sub_phase: 0f32,
+            lfo_phase: 0f32,
+            lfo_held: 0f32,
+            choke_group: None,
+            choked_at: None,
+            frozen: false,
+            frozen_frames: 0u64,
+            release_pending: false,
+            sustained: false,
+            cutoff_scale: 1f32,
+            filter: SvfCoefficients {
+                a1: 1f32,
+                a2: 0f32,
+                a3: 0f32,
+                input_gain: 1f32,
+                saturate: false,
+            },
+            left_filter: Svf::default(),
+            right_filter: Svf::default(),
+        }
+    }
+
+    // takes over the buffers of a finished tone, emptied, so they need not be allocated again
+    fn recycle(&mut self, mut finished: Tone) {
+        finished.unison.clear();
+        self.unison = finished.unison;
+        self.haas = finished.haas;
+    }
+
+    // glides exponentially, i.e. linearly in pitch, from `from` to the target increment
+    fn start_glide(&mut self, from: f32, glide_time: f32, samplerate: f32) {
+        let frames = (glide_time * samplerate) as u32;
+        if frames == 0 {
+            return;
+        }
+        self.phase_increment = from;
+        self.glide_ratio = (self.target_phase_increment / from).powf(1f32 / frames as f32);
+        self.glide_frames = frames;
+    }
+
+    // delays the channel the haas delay applies to, passing the other through
+    fn haas(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let haas = match &mut self.haas {
+            Some(haas) if !haas.buffer.is_empty() => haas,
+            _ => return (left, right),
+        };
+        let input = if haas.left { left } else { right };
+        let delayed = std::mem::replace(&mut haas.buffer[haas.position], input);
+        haas.position = (haas.position + 1) % haas.buffer.len();
+        if haas.left {
+            (delayed, right)
+        } else {
+            (left, delayed)
+        }
+    }
+
+    // frame the envelope counts from, the note-on moved on by the time spent frozen
+    fn envelope_start(&self) -> u64 {
+        self.time_pressed + self.frozen_frames
+    }
+
+    // starts the release now, or once freeze lets go of the voice
+    fn release(&mut self, frame: u64) {
+        if self.frozen {
+            self.release_pending = true;
+        } else {
+            self.time_released = Some(frame.max(self.envelope_start()));
+        }
+    }
+
+    fn advance_glide(&mut self) {
+        if self.glide_frames > 0 {
+            self.glide_frames -= 1;
+            self.phase_increment = if self.glide_frames == 0 {
+                self.target_phase_increment
+            } else {
+                self.phase_increment * self.glide_ratio
+            };
+        }
+    }
+}
+
+// changes kept before the oldest ones are dropped, allocated up front
+#[cfg(feature = "debug-automation")]
+const AUTOMATION_LOG_CAPACITY: usize = 4096;
+
+#[cfg(feature = "debug-automation")]
+macro_rules! control_values {
+    ($ports:expr, $($port:ident),* $(,)?) => {
+        [$((stringify!($port), *($ports.$port))),*]
+    };
+}
+
+#[cfg(feature = "debug-automation")]
+fn control_values(ports: &Ports) -> [(&'static str, f32); 133] {
+    control_values!(
+        ports,
+        attack, decay, sustain, release, brightness, gain, base_note, input_channel,
+        portamento_time, portamento_mode, glide_direction, min_note, max_note, fold_notes,
+        left_trim, right_trim, reset_phase_on_trigger, free_wheeling, offline_quality, pan, pan_law,
+        amp_keytrack, humanize, stereo_detune, dsf_variant, filter_cutoff, filter_resonance,
+        filter_keytrack, invert_left, invert_right, env_mode, tuning_hz, tuner_tone,
+        stack_same_note, bend_up_range, max_voices, steal_priority, decay_curve, delay_time,
+        delay_feedback, delay_mix, ping_pong, lfo_rate, lfo_depth, aftertouch_to_vibrato,
+        gate_threshold, gate_release, click, reverb_size, reverb_damp, reverb_mix, classic_mono,
+        brightness_decay, cc_source, cc_dest, tilt, glide_bend, strum, click_smooth, input_mix,
+        release_counts, mute, host_bpm, lfo_sync, lfo_division, channel_1_gain, channel_2_gain,
+        channel_3_gain, channel_4_gain, omni, lfo_retrigger, hpf_cutoff, max_noteons_per_block,
+        velocity_floor, choke_1, choke_1_low, choke_1_high, choke_2, choke_2_low, choke_2_high,
+        soft_clip, knee, clip_symmetry, metronome, host_bar_beat, host_speed, release_layer,
+        min_time, max_time, freeze, sub_level, sub_pan, noise_level, noise_pan, auto_glide,
+        bend_down_range, velocity_to_sustain, cpu_throttle, cpu_budget, unison, unison_detune,
+        detune_distribution, output_mode, mono_fold, self_osc, low_velocity_boost,
+        high_velocity_boost, decorrelation, end_threshold, unison_phase_spread, brightness_comp,
+        velocity_to_pan, glide_octave_snap, env_time, env_ratio, raw_osc, phase_align,
+        half_pedal, haas, brightness_slew, hold_last, velocity_humanize, pitch_env_amount,
+        pitch_env_time, velocity_to_glide, follower_attack, follower_release, follower_duck,
+        lfo_shape, release_curve, arp, arp_rate, gate_length,
+    )
+}
+
+#[cfg(feature = "debug-automation")]
+impl AutomationLog {
+    fn new() -> Self {
+        Self {
+            block: 0u64,
+            previous: Vec::new(),
+            changes: std::collections::VecDeque::with_capacity(AUTOMATION_LOG_CAPACITY),
+        }
+    }
+
+    fn record(&mut self, values: &[(&'static str, f32)]) {
+        // nothing compares equal to NaN, so the first block records every port
+        self.previous.resize(values.len(), f32::NAN);
+        for (previous, &(port, value)) in self.previous.iter_mut().zip(values) {
+            if *previous != value {
+                if self.changes.len() == AUTOMATION_LOG_CAPACITY {
+                    self.changes.pop_front();
+                }
+                self.changes.push_back(AutomationChange {
+                    block: self.block,
+                    port,
+                    value,
+                });
+                *previous = value;
+            }
+        }
+        self.block += 1;
+    }
+}
+
+impl Dsfsynth {
+    fn midi_note_to_phase_increment(&self, note: wmidi::Note) -> f32 {
+        if self.pitch_table_set {
+            let pitch = self.pitch_table[u8::from(note) as usize];
+            return std::f32::consts::TAU * pitch / self.samplerate;
+        }
+        let index = self.base_note as i32 + ((u8::from(note) as i32) - (self.base_note as i32)).rem_euclid(12i32);
+        let semitones = index as f32 - 69f32 + self.coarse_tuning + self.fine_tuning / 100f32;
+        let pitch = (semitones / 12f32).exp2() * self.tuning_hz;
+        std::f32::consts::TAU * pitch / self.samplerate
+    }
+
+    // the pitch the portamento mode has a new note glide from, before the glide direction
+    fn glide_origin(&self) -> Option<f32> {
+        let portamento_mode = if self.auto_glide {
+            PortamentoMode::Fingered
+        } else {
+            self.portamento_mode
+        };
+        match portamento_mode {
+            PortamentoMode::Off => None,
+            PortamentoMode::Fingered => self
+                .active_tones
+                .values()
+                .filter(|tone| tone.time_released.is_none())
+                .max_by_key(|tone| tone.time_pressed)
+                .map(|tone| tone.phase_increment),
+            PortamentoMode::Free => self.last_voice.map(|(voice, phase_increment)| {
+                self.active_tones
+                    .get(&voice)
+                    .map_or(phase_increment, |tone| tone.phase_increment)
+            }),
+            PortamentoMode::Cascade => self.last_pitch,
+        }
+    }
+
+    // the pitch a new note glides from, if it glides at all
+    fn glide_source(&self, target: f32) -> Option<f32> {
+        let source = self.glide_origin()?;
+        let glides = match self.glide_direction {
+            GlideDirection::Both => source != target,
+            GlideDirection::Up => source < target,
+            GlideDirection::Down => source > target,
+        };
+        if glides {
+            Some(self.glide_start(source))
+        } else {
+            None
+        }
+    }
+
+    // unbent increment a glide from `source` starts at, given the bend applied on top
+    fn glide_start(&self, source: f32) -> f32 {
+        match self.glide_bend {
+            GlideBend::FromBent => source,
+            GlideBend::FromUnbent => source / (self.pitch_bend_smoother.value / 12f32).exp2(),
+        }
+    }
+
+    // applies the note range, either dropping or folding notes outside of it
+    fn map_note(&self, note: wmidi::Note) -> Option<wmidi::Note> {
+        let value = u8::from(note);
+        if (self.min_note..=self.max_note).contains(&value) {
+            Some(note)
+        } else if self.fold_notes {
+            fold_note(value, self.min_note, self.max_note)
+                .and_then(|note| wmidi::Note::try_from(note).ok())
+        } else {
+            None
+        }
+    }
+
+    fn note_on(&mut self, channel: wmidi::Channel, note: wmidi::Note, velocity: wmidi::Velocity) {
+        let key = note;
+        let note = if let Some(note) = self.map_note(note) {
+            note
+        } else {
+            return;
+        };
+        let key_position = midi_val_to_ratio(u8::from(note) as f32);
+        let boost = self.low_velocity_boost
+            + (self.high_velocity_boost - self.low_velocity_boost) * key_position;
+        let velocity = midi_val_to_ratio(u8::from(velocity) as f32).powf((-boost).exp2());
+        let velocity = if self.velocity_humanize > 0f32 {
+            (velocity + self.velocity_humanize * self.rng.next_bipolar()).clamp(0f32, 1f32)
+        } else {
+            velocity
+        };
+        if self.classic_mono {
+            // the mono voice carries on into the new note instead of being released
+            self.held_voice = None;
+            self.mono_notes
+                .retain(|&(held_channel, held, _)| (held_channel, held) != (channel, key));
+            self.mono_notes.push((channel, key, note));
+            if let Some(voice) = self.mono_voice() {
+                self.mono_glide(voice, self.glide_time(velocity));
+                return;
+            }
+            // the single voice restarts, cutting off the release of the previous note
+            self.clear_voices();
+        } else {
+            self.release_held_voice();
+        }
+        if self.strum_frame == Some(self.current_frame) {
+            self.strum_index += 1;
+        } else {
+            self.strum_frame = Some(self.current_frame);
+            self.strum_index = 0u32;
+        }
+        let strum_offset = (self.strum * self.samplerate) as u64 * self.strum_index as u64;
+        let phase_increment = self.midi_note_to_phase_increment(note);
+        // the snapped pitch is the one the glide direction is checked against and the next
+        // note glides from
+        let phase_increment = match self.glide_origin() {
+            Some(origin) if self.glide_octave_snap && !self.classic_mono => {
+                nearest_octave(phase_increment, origin)
+            }
+            _ => phase_increment,
+        };
+        let mut tone = Tone::new(
+            u8::from(note),
+            phase_increment,
+            self.current_frame + strum_offset,
+            self.velocity_floor + (1f32 - self.velocity_floor) * velocity,
+        );
+        if let Some(finished) = self.tone_pool.pop() {
+            tone.recycle(finished);
+        }
+        tone.amplitude = decibel(
+            self.amp_keytrack * (u8::from(note) as f32 - KEYTRACK_CENTER as f32) / 12f32,
+        );
+        tone.key = u8::from(key);
+        tone.channel = channel;
+        tone.sustain_scale = 1f32 + self.velocity_to_sustain * (2f32 * tone.velocity - 1f32);
+        if self.velocity_to_pan != 0f32 {
+            let position = self.velocity_to_pan * (2f32 * tone.velocity - 1f32);
+            (tone.left_pan, tone.right_pan) = unity_pan_gains(position, PanLaw::Minus3Db);
+        }
+        // notes further from the centre get a longer delay, high notes on the left channel so
+        // they sound from the right and low notes the other way round
+        let spread = ((u8::from(note) as f32 - KEYTRACK_CENTER as f32) / 64f32).clamp(-1f32, 1f32);
+        let haas_frames = (self.haas / 1000f32 * spread.abs() * self.samplerate) as usize;
+        if haas_frames > 0 {
+            let mut buffer = tone.haas.take().map_or_else(Vec::new, |haas| haas.buffer);
+            buffer.clear();
+            // room for the delay of the outermost notes, so that the buffer fits any note
+            // the voice plays later on
+            buffer.reserve((self.haas / 1000f32 * self.samplerate) as usize);
+            buffer.resize(haas_frames, 0f32);
+            tone.haas = Some(Haas {
+                buffer,
+                position: 0usize,
+                left: spread > 0f32,
+            });
+        } else if let Some(haas) = &mut tone.haas {
+            // an empty buffer passes the tone through and is kept for the next note to reuse
+            haas.buffer.clear();
+        }
+        tone.choke_group = self.choke_groups.iter().position(|group| {
+            group.is_some_and(|(low, high)| (low..=high).contains(&u8::from(note)))
+        });
+        if tone.choke_group.is_some() {
+            for other in self.active_tones.values_mut() {
+                if other.choke_group == tone.choke_group && other.choked_at.is_none() {
+                    other.choked_at = Some(self.current_frame);
                 }
-                MidiMessage::NoteOff(channel, note, _velocity) => {
-                    if channel == self.input_channel {
-                        if let Some(tone) = self.active_tones.get_mut(&(u8::from(note))) {
-                            tone.time_released = Some(self.current_frame);
+            }
+        }
+        tone.env_mode = self.env_mode;
+        tone.cutoff_scale = (self.filter_keytrack
+            * (u8::from(note) as f32 - KEYTRACK_CENTER as f32)
+            / 12f32)
+            .exp2();
+        tone.filter = svf_coefficients(
+            self.filter_cutoff * tone.cutoff_scale,
+            self.filter_resonance,
+            self.self_osc,
+            self.samplerate,
+        );
+        if self.self_osc {
+            tone.left_filter.kick();
+            tone.right_filter.kick();
+        }
+        tone.lfo_held = self.lfo_rng.next_bipolar();
+        tone.attack_scale = 1f32 + self.humanize * self.rng.next_bipolar();
+        tone.release_scale = 1f32 + self.humanize * self.rng.next_bipolar();
+        if !self.reset_phase_on_trigger {
+            tone.phase = self.master_phase;
+            tone.right_phase = self.master_phase;
+        }
+        if self.phase_align {
+            let phase = (tone.time_pressed as f64 * phase_increment as f64)
+                .rem_euclid(std::f64::consts::TAU) as f32;
+            tone.phase = phase;
+            tone.right_phase = phase;
+        }
+        // a single unison voice must leave the copies empty, also for a tone taken from the pool,
+        // so that it keeps sounding bit for bit as before unison was added
+        if self.unison > 1 {
+            // at full spread the copies start evenly spaced around the cycle
+            let phase_spread = if self.phase_align { 0f32 } else { self.unison_phase_spread };
+            let phase_step = phase_spread * std::f32::consts::TAU / self.unison as f32;
+            for index in 0..self.unison {
+                let phase_offset = phase_step * index as f32;
+                let offset = self.unison_offset(index);
+                tone.unison.push(UnisonCopy {
+                    ratio: (offset * self.unison_detune / 1200f32).exp2(),
+                    phase: (tone.phase + phase_offset).rem_euclid(std::f32::consts::TAU),
+                    right_phase: (tone.right_phase + phase_offset)
+                        .rem_euclid(std::f32::consts::TAU),
+                });
+            }
+        }
+        if let Some(source) = self.glide_source(phase_increment).filter(|_| !self.classic_mono) {
+            tone.start_glide(source, self.glide_time(velocity), self.samplerate);
+        }
+        if !self.stack_same_note {
+            // one at a time, so that the restruck voices need not be collected
+            while let Some(voice) = self.note_voices.get(&u8::from(key)).and_then(|voices| {
+                voices.iter().copied().find(|voice| {
+                    self.active_tones.get(voice).is_some_and(|tone| tone.channel == channel)
+                })
+            }) {
+                self.remove_voice(voice);
+            }
+        }
+        while self.counted_voices() >= self.voice_limit() {
+            if let Some(victim) = self.voice_to_steal(u8::from(note)) {
+                self.remove_voice(victim);
+            } else {
+                break;
+            }
+        }
+        let voice = self.next_voice_id;
+        self.next_voice_id += 1;
+        self.active_tones.insert(voice, tone);
+        self.note_voices.entry(u8::from(key)).or_default().push(voice);
+        self.last_voice = Some((voice, phase_increment));
+        self.last_pitch = Some(phase_increment);
+    }
+
+    // the note-off goes by the key, so that it is not mapped again with a note range that
+    // may have changed since the note-on
+    fn note_off(&mut self, channel: wmidi::Channel, note: wmidi::Note, velocity: wmidi::Velocity) {
+        let release_noise = self.release_layer * midi_val_to_ratio(u8::from(velocity) as f32);
+        if self.classic_mono {
+            let held = |&(held_channel, held, _): &(wmidi::Channel, wmidi::Note, wmidi::Note)| {
+                (held_channel, held) == (channel, note)
+            };
+            if self.mono_notes.iter().any(held) {
+                self.release_noise += release_noise;
+            }
+            self.mono_notes.retain(|entry| !held(entry));
+            if let Some(voice) = self.mono_voice() {
+                if self.mono_notes.is_empty() && self.hold_last {
+                    self.held_voice = Some(voice);
+                } else if self.mono_notes.is_empty() {
+                    let holds = self.pedal_holds();
+                    let release_scale = self.pedal_release_scale();
+                    if let Some(tone) = self.active_tones.get_mut(&voice) {
+                        if holds {
+                            tone.sustained = true;
+                        } else {
+                            tone.release_scale *= release_scale;
+                            tone.release(self.current_frame);
                         }
+                        self.last_pitch = Some(tone.target_phase_increment);
+                    }
+                } else {
+                    self.mono_glide(voice, self.portamento_time);
+                }
+            }
+            return;
+        }
+        let voices = if let Some(voices) = self.note_voices.get(&u8::from(note)) {
+            voices
+        } else {
+            return;
+        };
+        // releases the oldest held voice of the channel; a repeated note-off must not restart
+        // the release of a voice that is already releasing from the sustain level
+        let held = voices.iter().copied().find(|voice| {
+            self.active_tones.get(voice).is_some_and(|tone| {
+                tone.time_released.is_none()
+                    && !tone.release_pending
+                    && !tone.sustained
+                    && tone.env_mode == EnvelopeMode::Gate
+                    && tone.channel == channel
+            })
+        });
+        if let Some(voice) = held.filter(|&voice| self.hold_last && !self.other_keys_held(voice)) {
+            self.held_voice = Some(voice);
+            return;
+        }
+        let holds = self.pedal_holds();
+        let release_scale = self.pedal_release_scale();
+        if let Some(tone) = held.and_then(|voice| self.active_tones.get_mut(&voice)) {
+            if holds {
+                tone.sustained = true;
+            } else {
+                tone.release_scale *= release_scale;
+                tone.release(self.current_frame);
+            }
+            self.last_pitch = Some(tone.phase_increment);
+            self.release_noise += release_noise;
+        }
+    }
+
+    // whether any key other than the one of `voice` is still down
+    fn other_keys_held(&self, voice: u64) -> bool {
+        self.active_tones.iter().any(|(&other, tone)| {
+            other != voice
+                && Some(other) != self.held_voice
+                && tone.time_released.is_none()
+                && !tone.release_pending
+                && !tone.sustained
+                && tone.env_mode == EnvelopeMode::Gate
+        })
+    }
+
+    // lets go of the voice hold_last kept sounding
+    fn release_held_voice(&mut self) {
+        let held = self.held_voice.take();
+        if let Some(tone) = held.and_then(|voice| self.active_tones.get_mut(&voice)) {
+            tone.release(self.current_frame);
+        }
+    }
+
+    // whether the damper pedal keeps released notes sounding; with half pedaling only a
+    // fully pressed pedal holds them
+    fn pedal_holds(&self) -> bool {
+        if self.half_pedal {
+            self.sustain_pedal >= 1f32
+        } else {
+            self.sustain_pedal >= 0.5
+        }
+    }
+
+    // lengthening of the release of notes let go of while the pedal is partly pressed,
+    // growing exponentially with its position
+    fn pedal_release_scale(&self) -> f32 {
+        if self.half_pedal {
+            HALF_PEDAL_MAX_RELEASE_SCALE.powf(self.sustain_pedal)
+        } else {
+            1f32
+        }
+    }
+
+    fn set_sustain_pedal(&mut self, value: u8) {
+        self.sustain_pedal = midi_val_to_ratio(value as f32);
+        if self.pedal_holds() {
+            return;
+        }
+        let release_scale = self.pedal_release_scale();
+        for tone in self.active_tones.values_mut().filter(|tone| tone.sustained) {
+            tone.sustained = false;
+            tone.release_scale *= release_scale;
+            tone.release(self.current_frame);
+        }
+    }
+
+    // freezing holds the sounding voices at their envelope level until unfrozen, when they
+    // carry on and the note-offs they got in the meantime release them
+    fn set_freeze(&mut self, freeze: bool) {
+        self.freeze = freeze;
+        for tone in self.active_tones.values_mut() {
+            tone.frozen = freeze;
+            if !freeze && tone.release_pending {
+                tone.release_pending = false;
+                tone.time_released = Some(self.current_frame.max(tone.envelope_start()));
+            }
+        }
+    }
+
+    // the voice of classic mono mode while a key is held
+    fn mono_voice(&self) -> Option<u64> {
+        self.active_tones
+            .iter()
+            .find(|(_, tone)| tone.time_released.is_none())
+            .map(|(voice, _)| *voice)
+    }
+
+    // portamento time of a glide into a note of the given velocity
+    fn glide_time(&self, velocity: f32) -> f32 {
+        self.portamento_time
+            * VELOCITY_GLIDE_MAX_SCALE.powf(-self.velocity_to_glide * (2f32 * velocity - 1f32))
+    }
+
+    // moves the mono voice to the lowest held note without retriggering its envelope
+    fn mono_glide(&mut self, voice: u64, glide_time: f32) {
+        let lowest = self
+            .mono_notes
+            .iter()
+            .map(|&(_, key, held)| (key, held))
+            .min_by_key(|&(_, held)| u8::from(held));
+        let (key, note) = if let Some(lowest) = lowest {
+            lowest
+        } else {
+            return;
+        };
+        let target = self.midi_note_to_phase_increment(note);
+        let from = if let Some(tone) = self.active_tones.get(&voice) {
+            self.glide_start(tone.phase_increment)
+        } else {
+            return;
+        };
+        let tone = if let Some(tone) = self.active_tones.get_mut(&voice) {
+            tone
+        } else {
+            return;
+        };
+        if (tone.key, tone.note) == (u8::from(key), u8::from(note)) {
+            return;
+        }
+        let previous = tone.key;
+        tone.note = u8::from(note);
+        tone.key = u8::from(key);
+        let target = if self.glide_octave_snap {
+            nearest_octave(target, from)
+        } else {
+            target
+        };
+        tone.target_phase_increment = target;
+        tone.phase_increment = target;
+        tone.glide_frames = 0u32;
+        tone.start_glide(from, glide_time, self.samplerate);
+        if let Some(voices) = self.note_voices.get_mut(&previous) {
+            voices.retain(|&other| other != voice);
+        }
+        self.note_voices.entry(u8::from(key)).or_default().push(voice);
+        self.last_voice = Some((voice, target));
+        self.last_pitch = Some(target);
+    }
+
+    fn key_pressure(&mut self, channel: wmidi::Channel, note: wmidi::Note, pressure: f32) {
+        if let Some(voices) = self.note_voices.get(&u8::from(note)) {
+            for voice in voices {
+                if let Some(tone) = self.active_tones.get_mut(voice) {
+                    if tone.channel == channel {
+                        tone.pressure = pressure;
                     }
                 }
-                _ => (),
             }
         }
+    }
+
+    fn control_change(&mut self, control: u8, value: u8) {
+        // a mapped controller only drives its destination, even one that is hard-wired
+        // to something else when unmapped
+        if self.cc_mapping.1 != CcDestination::Off && control == self.cc_mapping.0 {
+            self.mapped_cc_value = Some(value);
+            self.apply_cc_mapping();
+            return;
+        }
+        match control {
+            101 => self.rpn.0 = value,
+            100 => self.rpn.1 = value,
+            // selecting a non-registered parameter deselects the registered one
+            98 | 99 => self.rpn = RPN_NULL,
+            1 => self.mod_wheel = midi_val_to_ratio(value as f32),
+            11 => self.expression = midi_val_to_ratio(value as f32),
+            64 => self.set_sustain_pedal(value),
+            6 => {
+                self.rpn_data = (value as u16) << 7 | (self.rpn_data & 0x7f);
+                self.apply_rpn();
+            }
+            38 => {
+                self.rpn_data = (self.rpn_data & !0x7f) | value as u16;
+                self.apply_rpn();
+            }
+            _ => (),
+        }
+    }
+
+    // scales the mapped controller over the full range of its destination's port
+    fn apply_cc_mapping(&mut self) {
+        let value = if let Some(value) = self.mapped_cc_value {
+            midi_val_to_ratio(value as f32)
+        } else {
+            return;
+        };
+        match self.cc_mapping.1 {
+            CcDestination::Off => (),
+            CcDestination::Brightness => self.brightness = value,
+            CcDestination::Gain => self.gain = -50f32 + 50f32 * value,
+            CcDestination::Cutoff => self.filter_cutoff = 20f32 * 1000f32.powf(value),
+            CcDestination::Resonance => self.filter_resonance = value,
+            CcDestination::Pan => self.pan = value * 2f32 - 1f32,
+        }
+    }
+
+    // brightness with the mod wheel applied
+    fn effective_brightness(&self) -> f32 {
+        self.brightness + (1f32 - self.brightness) * self.mod_wheel
+    }
+
+    // gain in dB with the expression applied, for the telemetry only; the output level stays
+    // at the gain, so that hosts sending an expression of 0 do not turn the synth silent
+    fn effective_gain(&self) -> f32 {
+        self.gain - EXPRESSION_RANGE * (1f32 - self.expression)
+    }
+
+    fn apply_rpn(&mut self) {
+        match self.rpn {
+            RPN_FINE_TUNING => {
+                self.fine_tuning = (self.rpn_data as f32 - 8192f32) / 8192f32 * 100f32;
+            }
+            RPN_COARSE_TUNING => {
+                self.coarse_tuning = (self.rpn_data >> 7) as f32 - 64f32;
+            }
+            _ => (),
+        }
+    }
+
+    // replaces the pitch of every note with the given frequencies in Hz, or goes back to the
+    // tuning formula for None; playing voices keep their pitch
+    pub fn set_pitch_table(&mut self, table: Option<[f32; 128]>) {
+        if let Some(table) = table {
+            *self.pitch_table = table;
+        }
+        self.pitch_table_set = table.is_some();
+    }
+
+    // applies a midi tuning standard message to the pitch table, either a real-time single
+    // note tuning change or a bulk tuning dump; playing voices keep their pitch
+    fn tuning_sysex(&mut self, data: &[u8]) {
+        match data {
+            // device, program and count, then a note and its frequency for each change
+            [MTS_REALTIME, _, MTS_ID, MTS_SINGLE_NOTE, _, count, changes @ ..] => {
+                for change in changes.chunks_exact(4).take(*count as usize) {
+                    self.retune_note(change[0], [change[1], change[2], change[3]]);
+                }
+            }
+            // device, program and name, then the frequency of every note and a checksum
+            [MTS_NON_REALTIME, _, MTS_ID, MTS_BULK_DUMP, _, rest @ ..]
+                if rest.len() >= MTS_NAME_LENGTH + 3 * 128 =>
+            {
+                let frequencies = rest[MTS_NAME_LENGTH..].chunks_exact(3).take(128);
+                for (note, frequency) in frequencies.enumerate() {
+                    self.retune_note(note as u8, [frequency[0], frequency[1], frequency[2]]);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // sets the pitch of `note` to a tuning standard frequency, a semitone followed by a 14 bit
+    // fraction of a semitone; 7f 7f 7f leaves the note as it is. the first change fills the
+    // table with the pitches of the tuning formula, so the other notes stay where they were
+    fn retune_note(&mut self, note: u8, frequency: [u8; 3]) {
+        let note = match wmidi::Note::try_from(note) {
+            Ok(note) if frequency != [0x7f; 3] => u8::from(note) as usize,
+            _ => return,
+        };
+        if !self.pitch_table_set {
+            let table = std::array::from_fn(|note| {
+                let note = wmidi::Note::try_from(note as u8).unwrap();
+                self.midi_note_to_phase_increment(note) * self.samplerate / std::f32::consts::TAU
+            });
+            self.set_pitch_table(Some(table));
+        }
+        let fraction = ((frequency[1] as u16) << 7 | frequency[2] as u16) as f32 / 16384f32;
+        let semitones = frequency[0] as f32 + fraction - 69f32;
+        self.pitch_table[note] = MTS_A4_FREQUENCY * (semitones / 12f32).exp2();
+    }
+
+    // moves the stereo output between the front and the rear pair; front_back runs from -1
+    // at the front to 1 at the rear, and the pan keeps placing the voices from left to right
+    #[cfg(feature = "surround")]
+    fn place_front_back(&mut self, ports: &mut Ports) {
+        let (front_target, rear_target) = pan_gains(*(ports.front_back), PanLaw::Minus3Db);
+        let (previous_front, previous_rear) = self.front_rear_gains;
+        let frames = ports.left_audio_output.len();
+        for frame in 0..frames {
+            let progress = (frame + 1) as f32 / frames as f32;
+            let front = previous_front + (front_target - previous_front) * progress;
+            let rear = previous_rear + (rear_target - previous_rear) * progress;
+            let (left, right) = (ports.left_audio_output[frame], ports.right_audio_output[frame]);
+            ports.rear_left_audio_output[frame] = left * rear;
+            ports.rear_right_audio_output[frame] = right * rear;
+            ports.left_audio_output[frame] = left * front;
+            ports.right_audio_output[frame] = right * front;
+        }
+        self.front_rear_gains = (front_target, rear_target);
+    }
+
+    // control port changes recorded so far, oldest first
+    #[cfg(feature = "debug-automation")]
+    pub fn automation_changes(&self) -> impl Iterator<Item = &AutomationChange> {
+        self.automation.changes.iter()
+    }
+
+    // current envelope level of the newest voice playing `note`, or None once it has
+    // finished or if the note is not playing
+    pub fn voice_env_level(&self, note: wmidi::Note) -> Option<f32> {
+        self.note_voices
+            .get(&u8::from(note))?
+            .iter()
+            .rev()
+            .filter_map(|voice| self.active_tones.get(voice))
+            .find_map(|tone| envelope(tone, self.current_frame, &self.adsr, self.samplerate))
+    }
+
+    // glide state of the newest voice playing `note`, or None if the note is not playing
+    pub fn voice_glide(&self, note: wmidi::Note) -> Option<GlideState> {
+        let tone = self
+            .note_voices
+            .get(&u8::from(note))?
+            .iter()
+            .rev()
+            .find_map(|voice| self.active_tones.get(voice))?;
+        Some(GlideState {
+            gliding: tone.glide_frames > 0,
+            pitch: tone.phase_increment * self.samplerate / std::f32::consts::TAU,
+        })
+    }
+
+    // detune of unison copy `index` from -1 to 1, as a fraction of the detune range
+    fn unison_offset(&mut self, index: usize) -> f32 {
+        match self.detune_distribution {
+            DetuneDistribution::Even => 2f32 * index as f32 / (self.unison - 1) as f32 - 1f32,
+            DetuneDistribution::Random => self.rng.next_bipolar(),
+            // the mean of three uniform values approximates a normal distribution
+            DetuneDistribution::Gaussian => {
+                (self.rng.next_bipolar() + self.rng.next_bipolar() + self.rng.next_bipolar())
+                    / 3f32
+            }
+        }
+    }
+
+    // rough cost of one voice per sample, in oscillator evaluations
+    fn voice_cost(&self) -> f32 {
+        let oscillators = if self.stereo_detune == 0f32 { 1f32 } else { 2f32 };
+        let filter = if self.filter_cutoff < FILTER_MAX_CUTOFF { 1f32 } else { 0f32 };
+        oscillators * (self.unison * self.oversampling as usize) as f32 + filter
+    }
+
+    // rough cost per sample of the effects on the summed output
+    fn effects_cost(&self) -> f32 {
+        let reverb = if self.reverb_mix > 0f32 { 8f32 } else { 0f32 };
+        let delay = if self.delay_mix > 0f32 { 1f32 } else { 0f32 };
+        let decorrelation = if self.decorrelation > 0f32 { 1f32 } else { 0f32 };
+        reverb + delay + decorrelation
+    }
+
+    // polyphony limit, lowered by the cpu throttle to what fits the budget
+    fn voice_limit(&self) -> usize {
+        if !self.cpu_throttle {
+            return self.max_voices;
+        }
+        let affordable = (self.cpu_budget - self.effects_cost()) / self.voice_cost();
+        (affordable.max(1f32) as usize).min(self.max_voices)
+    }
+
+    fn counts_against_polyphony(&self, tone: &Tone) -> bool {
+        self.release_counts || tone.time_released.is_none()
+    }
+
+    fn counted_voices(&self) -> usize {
+        self.active_tones
+            .values()
+            .filter(|tone| self.counts_against_polyphony(tone))
+            .count()
+    }
+
+    // only voices that take up a slot are stolen, so a release that does not count rings on
+    fn voice_to_steal(&self, note: u8) -> Option<u64> {
+        let voices = self
+            .active_tones
+            .iter()
+            .filter(|(_, tone)| self.counts_against_polyphony(tone));
+        match self.steal_priority {
+            StealPriority::Oldest => voices
+                .min_by_key(|(voice, tone)| (tone.time_pressed, **voice))
+                .map(|(voice, _)| *voice),
+            StealPriority::Quietest => voices
+                .map(|(voice, tone)| {
+                    let level = envelope(tone, self.current_frame, &self.adsr, self.samplerate);
+                    (*voice, level.unwrap_or(0f32))
+                })
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(voice, _)| voice),
+            StealPriority::Nearest => voices
+                .min_by_key(|(voice, tone)| ((tone.note as i32 - note as i32).abs(), **voice))
+                .map(|(voice, _)| *voice),
+        }
+    }
+
+    fn remove_voice(&mut self, voice: u64) {
+        if let Some(tone) = self.active_tones.remove(&voice) {
+            // the emptied list stays, so that playing the key again does not allocate one
+            if let Some(voices) = self.note_voices.get_mut(&tone.key) {
+                voices.retain(|&other| other != voice);
+            }
+            self.tone_pool.push(tone);
+        }
+    }
+
+    // clearing keeps the capacity of the maps, and the voices go back to the pool
+    fn clear_voices(&mut self) {
+        self.tone_pool.extend(self.active_tones.drain().map(|(_, tone)| tone));
+        self.note_voices.values_mut().for_each(Vec::clear);
+    }
+
+    // while the arpeggiator is on, it takes the keys and plays its own notes from them
+    fn press_key(
+        &mut self,
+        channel: wmidi::Channel,
+        note: wmidi::Note,
+        velocity: wmidi::Velocity,
+    ) {
+        if self.arp_enabled {
+            self.arpeggiator.press(channel, note, velocity, self.current_frame);
+        } else {
+            self.note_on(channel, note, velocity);
+        }
+    }
+
+    fn release_key(
+        &mut self,
+        channel: wmidi::Channel,
+        note: wmidi::Note,
+        velocity: wmidi::Velocity,
+    ) {
+        if self.arp_enabled {
+            self.arpeggiator.release(channel, note);
+        } else {
+            self.note_off(channel, note, velocity);
+        }
+    }
+
+    // releases the sounding arpeggiator note, if its gate ends at or before the current
+    // frame, and plays the next step if that is due
+    fn advance_arpeggiator(&mut self) {
+        let frame = self.current_frame;
+        if let Some((channel, note, _)) =
+            self.arpeggiator.sounding.filter(|&(_, _, release)| release <= frame)
+        {
+            let velocity = wmidi::Velocity::try_from(DEFAULT_RELEASE_VELOCITY).unwrap();
+            self.note_off(channel, note, velocity);
+            self.arpeggiator.sounding = None;
+        }
+        let step = match self.arpeggiator.next_step {
+            Some(step) if step <= frame => step,
+            _ => return,
+        };
+        let position = self.arpeggiator.position % self.arpeggiator.keys.len();
+        let (channel, note, velocity) = self.arpeggiator.keys[position];
+        // a gate running into the next step still ends just before it, so that every step
+        // plays its own note
+        let step_frames = (60f32 * self.samplerate / (self.host_bpm * self.arp_rate)) as u64;
+        let step_frames = step_frames.max(1u64);
+        let gate_frames = ((step_frames as f32 * self.gate_length) as u64).clamp(1, step_frames);
+        if let Some((channel, note, _)) = self.arpeggiator.sounding.take() {
+            let velocity = wmidi::Velocity::try_from(DEFAULT_RELEASE_VELOCITY).unwrap();
+            self.note_off(channel, note, velocity);
+        }
+        self.note_on(channel, note, velocity);
+        self.arpeggiator.sounding = Some((channel, note, step + gate_frames));
+        self.arpeggiator.position = position + 1;
+        self.arpeggiator.next_step = Some(step + step_frames);
+    }
+
+    // releases the sounding arpeggiator note and forgets the held keys
+    fn stop_arpeggiator(&mut self) {
+        if let Some((channel, note, _)) = self.arpeggiator.sounding.take() {
+            let velocity = wmidi::Velocity::try_from(DEFAULT_RELEASE_VELOCITY).unwrap();
+            self.note_off(channel, note, velocity);
+        }
+        self.arpeggiator.keys.clear();
+        self.arpeggiator.position = 0usize;
+        self.arpeggiator.next_step = None;
+    }
+
+    // renders the block from `rendered` up to `frame`, stopping on the way wherever the
+    // arpeggiator plays or releases a note
+    fn render_until(&mut self, ports: &mut Ports, rendered: &mut usize, frame: usize) {
+        loop {
+            let end = self.current_frame + (frame - *rendered) as u64;
+            let until = match self.arpeggiator.next_event() {
+                Some(event) if event < end => {
+                    *rendered + event.saturating_sub(self.current_frame) as usize
+                }
+                _ => frame,
+            };
+            self.render(
+                &ports.left_audio_input[*rendered..until],
+                &ports.right_audio_input[*rendered..until],
+                &mut ports.left_audio_output[*rendered..until],
+                &mut ports.right_audio_output[*rendered..until],
+            );
+            *rendered = until;
+            if until == frame {
+                return;
+            }
+            self.advance_arpeggiator();
+        }
+    }
+
+    fn receives(&self, channel: wmidi::Channel) -> bool {
+        self.omni || channel == self.input_channel
+    }
+
+    fn handle_message(&mut self, message: MidiMessage) {
+        match message {
+            // a note-on with velocity 0 is a note-off without a release velocity
+            MidiMessage::NoteOn(channel, note, velocity)
+                if self.receives(channel) && u8::from(velocity) == 0u8 =>
+            {
+                let velocity = wmidi::Velocity::try_from(DEFAULT_RELEASE_VELOCITY).unwrap();
+                self.release_key(channel, note, velocity);
+            }
+            MidiMessage::NoteOn(channel, note, velocity)
+                if self.receives(channel) && self.block_noteons < self.max_noteons_per_block =>
+            {
+                self.block_noteons += 1;
+                self.press_key(channel, note, velocity);
+            }
+            MidiMessage::NoteOff(channel, note, velocity) if self.receives(channel) => {
+                self.release_key(channel, note, velocity);
+            }
+            MidiMessage::PitchBendChange(channel, bend) if self.receives(channel) => {
+                self.pitch_bend = midi_pitch_bend_to_ratio(bend);
+            }
+            MidiMessage::ChannelPressure(channel, pressure) if self.receives(channel) => {
+                self.channel_pressure = midi_val_to_ratio(u8::from(pressure) as f32);
+            }
+            MidiMessage::ControlChange(channel, control, value) if self.receives(channel) => {
+                self.control_change(u8::from(control), u8::from(value));
+            }
+            MidiMessage::PolyphonicKeyPressure(channel, note, pressure)
+                if self.receives(channel) =>
+            {
+                self.key_pressure(channel, note, midi_val_to_ratio(u8::from(pressure) as f32));
+            }
+            MidiMessage::OwnedSysEx(data) => self.tuning_sysex(U7::data_to_bytes(&data)),
+            _ => (),
+        }
+    }
+
+    // moves every smoothed value straight to its target, and the gate and the filters after
+    // the effects to where silence takes them, so that skipping quiet blocks leaves the same
+    // state behind as running them
+    fn settle_smoothers(
+        &mut self,
+        bend_target: f32,
+        left_gain: f32,
+        right_gain: f32,
+        mute_gain: f32,
+    ) {
+        self.pitch_bend_smoother.value = bend_target;
+        self.left_gain_smoother.value = left_gain;
+        self.right_gain_smoother.value = right_gain;
+        self.brightness_smoother.value = self.effective_brightness();
+        self.slewed_brightness = self.effective_brightness();
+        self.variant_fade.progress = 1f32;
+        self.mute_smoother.value = mute_gain;
+        self.gate.detector = 0f32;
+        self.gate.gain = 0f32;
+        self.tilt_filter = Tilt::default();
+        self.highpass = Highpass::default();
+    }
+
+    // renders the frames from current_frame on into the output slices, mixing in the
+    // matching frames of the external input
+    fn render(
+        &mut self,
+        left_input: &[f32],
+        right_input: &[f32],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) {
+        // folded to mono, every pan position plays at the level of the centre, so panning
+        // does not change the mono level
+        let (left_pan, right_pan) = match self.output_mode {
+            OutputMode::Stereo => unity_pan_gains(self.pan, self.pan_law),
+            OutputMode::Mono => unity_pan_gains(0f32, self.pan_law),
+        };
+        let mono_fold_gain = match self.mono_fold {
+            MonoFold::Average => 0.5,
+            MonoFold::EqualPower => std::f32::consts::FRAC_1_SQRT_2,
+        };
+        let left_gain = decibel(self.left_trim) * left_pan;
+        let right_gain = decibel(self.right_trim) * right_pan;
+        let master_phase_increment = std::f32::consts::TAU * MASTER_PHASE_PITCH / self.samplerate;
+        let filter_enabled = self.filter_cutoff < FILTER_MAX_CUTOFF;
+        if filter_enabled {
+            for tone in self.active_tones.values_mut() {
+                tone.filter = svf_coefficients(
+                    self.filter_cutoff * tone.cutoff_scale,
+                    self.filter_resonance,
+                    self.self_osc,
+                    self.samplerate,
+                );
+            }
+        }
+        let gain = decibel(self.gain);
+        let target_brightness = self.effective_brightness();
+        let tuner_phase_increment = std::f32::consts::TAU * self.tuning_hz / self.samplerate;
+        let variant_fade_step = 1f32 / (VARIANT_FADE_TIME * self.samplerate);
+        let brightness_step = self.brightness_slew / self.samplerate;
+        let left_polarity = if self.invert_left { -1f32 } else { 1f32 };
+        let right_polarity = if self.invert_right { -1f32 } else { 1f32 };
+        let delay_frames = (self.delay_time * self.samplerate) as usize;
+        let lfo_rate = if self.lfo_sync {
+            self.host_bpm / 60f32 / lfo_division_beats(self.lfo_division)
+        } else {
+            self.lfo_rate
+        };
+        let lfo_phase_increment = std::f32::consts::TAU * lfo_rate / self.samplerate;
+        let gate_threshold = decibel(self.gate_threshold);
+        let gate_release_step = 1f32 / (self.gate_release * self.samplerate);
+        let follower_attack = 1f32 - (-1f32 / (self.follower_attack * self.samplerate)).exp();
+        let follower_release = 1f32 - (-1f32 / (self.follower_release * self.samplerate)).exp();
+        let click_frames = (CLICK_TIME * self.samplerate) as u64;
+        let smooth_frames = (self.click_smooth * self.samplerate) as u64;
+        let pitch_env_frames = (self.pitch_env_time * self.samplerate).max(1f32);
+        let choke_frames = CHOKE_TIME * self.samplerate;
+        let mute_gain = if self.mute { 0f32 } else { 1f32 };
+        let channel_gains = self.channel_gains.map(decibel);
+        let beat_increment =
+            (self.host_bpm * self.host_speed) as f64 / 60f64 / self.samplerate as f64;
+        // the layers pan as a balance, so centred they are at full level on both sides like
+        // the shepard tone
+        let (left_sub, right_sub) = pan_gains(self.sub_pan, PanLaw::ZeroDb);
+        let (left_noise, right_noise) = pan_gains(self.noise_pan, PanLaw::ZeroDb);
+        // bend in semitones, with separate ranges above and below the centre
+        let bend_target = if self.pitch_bend < 0f32 {
+            self.pitch_bend * self.bend_down_range
+        } else {
+            self.pitch_bend * self.bend_up_range
+        };
+        let release_noise_decay = (-1f32 / (RELEASE_LAYER_TIME * self.samplerate)).exp();
+        let hpf_coefficient = (-std::f32::consts::TAU * self.hpf_cutoff / self.samplerate).exp();
+        let tilt_coefficient = 1f32 - (-std::f32::consts::TAU * TILT_PIVOT / self.samplerate).exp();
+        let tilt_low = decibel(-TILT_MAX_GAIN * self.tilt);
+        let tilt_high = decibel(TILT_MAX_GAIN * self.tilt);
+        // the right channel copy runs this many cents above the left one
+        let stereo_ratio = (self.stereo_detune / 1200f32).exp2();
+        #[cfg(debug_assertions)]
+        let dc_coefficient = 1f32 - (-1f32 / (DC_GUARD_TIME * self.samplerate)).exp();
+
+        // the first block after activate starts at the targets instead of ramping from values
+        // left over from before, or set while the plugin was inactive
+        if self.settle_pending {
+            self.settle_smoothers(bend_target, left_gain, right_gain, mute_gain);
+            self.settle_pending = false;
+        }
+
+        // once every delay line cell has been read back quiet, nothing can sound until the
+        // next note, so the per-sample work is skipped and only the free-running phases move on
+        if self.active_tones.is_empty()
+            && !self.tuner_tone
+            && self.input_mix == 0f32
+            && !self.metronome_enabled
+            && self.release_noise == 0f32
+            && self.quiet_frames
+                >= self.delay.left.len().max(self.reverb.length()).max(self.decorrelator.length())
+        {
+            let frames = left_output.len() as f32;
+            left_output.iter_mut().for_each(|sample| *sample = 0f32);
+            right_output.iter_mut().for_each(|sample| *sample = 0f32);
+            self.master_phase = (self.master_phase + master_phase_increment * frames)
+                .rem_euclid(std::f32::consts::TAU);
+            advance_lfo(
+                &mut self.lfo_phase,
+                &mut self.lfo_held,
+                lfo_phase_increment * frames,
+                &mut self.lfo_rng,
+            );
+            self.settle_smoothers(bend_target, left_gain, right_gain, mute_gain);
+            // the input is not mixed in, but it is still followed
+            for (&left, &right) in left_input.iter().zip(right_input.iter()) {
+                self.follower.process(left, right, follower_attack, follower_release);
+            }
+            self.clip_indicator
+                .process(false, left_output.len() as u32, self.samplerate);
+            self.current_frame += left_output.len() as u64;
+            return;
+        }
 
-        let mut frame_index = self.current_frame;
-        for (left_out_frame, right_out_frame) in Iterator::zip(
-            ports.left_audio_output.iter_mut(),
-            ports.right_audio_output.iter_mut(),
-        ) {
-            let mut value = 0f32;
-            let mut finished_tones = vec![];
-            for (note, tone) in self.active_tones.iter_mut() {
+        for (frame_offset, (left_out_frame, right_out_frame)) in Iterator::zip(
+            left_output.iter_mut(),
+            right_output.iter_mut(),
+        )
+        .enumerate()
+        {
+            let frame_index = self.current_frame + frame_offset as u64;
+            let mut left_value: Mix = 0.0;
+            let mut right_value: Mix = 0.0;
+            let mut left_raw: Mix = 0.0;
+            let mut right_raw: Mix = 0.0;
+            // interpolating the bend per sample keeps coarse bend messages from stepping
+            let bend = self.pitch_bend_smoother.next(bend_target);
+            let lfo = lfo_value(self.lfo_shape, self.lfo_phase, self.lfo_held);
+            let smoothed_brightness = self.brightness_smoother.next(target_brightness);
+            self.slewed_brightness += (smoothed_brightness - self.slewed_brightness)
+                .clamp(-brightness_step, brightness_step);
+            let smoothed_brightness = self.slewed_brightness;
+            let variant = self.variant_fade;
+            self.variant_fade.advance(variant_fade_step);
+            for (voice, tone) in self.active_tones.iter_mut() {
+                // strummed voices wait silently for their onset
+                if frame_index < tone.time_pressed {
+                    continue;
+                }
+                // vibrato depth in semitones, deepened by whichever aftertouch is stronger
+                let vibrato_depth = self.lfo_depth
+                    + self.aftertouch_to_vibrato * self.channel_pressure.max(tone.pressure);
+                let vibrato = if self.lfo_retrigger {
+                    let vibrato =
+                        vibrato_depth * lfo_value(self.lfo_shape, tone.lfo_phase, tone.lfo_held);
+                    advance_lfo(
+                        &mut tone.lfo_phase,
+                        &mut tone.lfo_held,
+                        lfo_phase_increment,
+                        &mut self.lfo_rng,
+                    );
+                    vibrato
+                } else {
+                    vibrato_depth * lfo
+                };
                 if let Some(envelope) = envelope(tone, frame_index, &self.adsr, self.samplerate) {
-                    value += shepard_tone(self.brightness, tone.phase, self.base_note, *note) * envelope * decibel(self.gain) * tone.velocity;
-                    tone.phase =
-                        (tone.phase + tone.phase_increment).rem_euclid(std::f32::consts::TAU);
+                    let channel_gain = channel_gains
+                        .get(tone.channel.index() as usize)
+                        .copied()
+                        .unwrap_or(1f32);
+                    let level = envelope * gain * tone.velocity * tone.amplitude * channel_gain;
+                    let level = if let Some(choked) = tone.choked_at {
+                        let progress = frame_index.saturating_sub(choked) as f32 / choke_frames;
+                        if progress >= 1f32 {
+                            self.finished_tones.push(*voice);
+                            continue;
+                        }
+                        level * (1f32 - progress)
+                    } else {
+                        level
+                    };
+                    let age = frame_index - tone.envelope_start();
+                    // sweeps linearly in semitones from the offset down to the note's pitch
+                    let pitch_env = if self.pitch_env_amount != 0f32
+                        && (age as f32) < pitch_env_frames
+                    {
+                        self.pitch_env_amount * (1f32 - age as f32 / pitch_env_frames)
+                    } else {
+                        0f32
+                    };
+                    let pitch_offset = bend + vibrato + pitch_env;
+                    let phase_increment = if pitch_offset == 0f32 {
+                        tone.phase_increment
+                    } else {
+                        tone.phase_increment * (pitch_offset / 12f32).exp2()
+                    };
+                    // even a zero attack ramps in over a few samples instead of jumping
+                    let level = if age < smooth_frames {
+                        level * age as f32 / smooth_frames as f32
+                    } else {
+                        level
+                    };
+                    let brightness = if self.brightness_decay == 0f32 {
+                        smoothed_brightness
+                    } else {
+                        smoothed_brightness
+                            * (-self.brightness_decay * age as f32 / self.samplerate).exp()
+                    };
+                    let (left, right) = if tone.unison.is_empty() {
+                        let left = oversampled_shepard_tone(
+                            brightness,
+                            tone.phase,
+                            phase_increment,
+                            self.base_note,
+                            tone.note,
+                            variant,
+                            self.oversampling,
+                        );
+                        let right = if self.stereo_detune == 0f32 {
+                            left
+                        } else {
+                            oversampled_shepard_tone(
+                                brightness,
+                                tone.right_phase,
+                                phase_increment * stereo_ratio,
+                                self.base_note,
+                                tone.note,
+                                variant,
+                                self.oversampling,
+                            )
+                        };
+                        (left, right)
+                    } else {
+                        // the copies are uncorrelated, so their sum is scaled to keep the power
+                        let copies = tone.unison.len() as f32;
+                        let (mut left, mut right) = (0f32, 0f32);
+                        for copy in tone.unison.iter_mut() {
+                            let increment = phase_increment * copy.ratio;
+                            let copy_left = oversampled_shepard_tone(
+                                brightness,
+                                copy.phase,
+                                increment,
+                                self.base_note,
+                                tone.note,
+                                variant,
+                                self.oversampling,
+                            );
+                            left += copy_left;
+                            right += if self.stereo_detune == 0f32 {
+                                copy_left
+                            } else {
+                                oversampled_shepard_tone(
+                                    brightness,
+                                    copy.right_phase,
+                                    increment * stereo_ratio,
+                                    self.base_note,
+                                    tone.note,
+                                    variant,
+                                    self.oversampling,
+                                )
+                            };
+                            copy.phase = (copy.phase + increment).rem_euclid(std::f32::consts::TAU);
+                            copy.right_phase = if self.stereo_detune == 0f32 {
+                                copy.phase
+                            } else {
+                                (copy.right_phase + increment * stereo_ratio)
+                                    .rem_euclid(std::f32::consts::TAU)
+                            };
+                        }
+                        (left / copies.sqrt(), right / copies.sqrt())
+                    };
+                    left_raw += left as Mix;
+                    right_raw += right as Mix;
+                    let (left, right) = if self.brightness_comp {
+                        let compensation = variant
+                            .blend(|variant| brightness_compensation(variant, brightness));
+                        (left * compensation, right * compensation)
+                    } else {
+                        (left, right)
+                    };
+                    let (left, right) = if filter_enabled {
+                        (
+                            tone.left_filter.lowpass(&tone.filter, left),
+                            tone.right_filter.lowpass(&tone.filter, right),
+                        )
+                    } else {
+                        (left, right)
+                    };
+                    let (left, right) =
+                        tone.haas(left * level * tone.left_pan, right * level * tone.right_pan);
+                    left_value += left as Mix;
+                    right_value += right as Mix;
+                    if self.sub_level > 0f32 {
+                        let sub = tone.sub_phase.sin() * self.sub_level * level;
+                        left_value += (sub * left_sub) as Mix;
+                        right_value += (sub * right_sub) as Mix;
+                        tone.sub_phase = (tone.sub_phase + phase_increment / 2f32)
+                            .rem_euclid(std::f32::consts::TAU);
+                    }
+                    if self.noise_level > 0f32 {
+                        let noise = self.rng.next_bipolar() * self.noise_level * level;
+                        left_value += (noise * left_noise) as Mix;
+                        right_value += (noise * right_noise) as Mix;
+                    }
+                    // a burst of noise fading out over the first few samples of the voice
+                    if self.click > 0f32 && age < click_frames {
+                        let fade = 1f32 - age as f32 / click_frames as f32;
+                        let click = self.rng.next_bipolar() * fade * self.click * gain * tone.velocity;
+                        left_value += click as Mix;
+                        right_value += click as Mix;
+                    }
+                    tone.phase = (tone.phase + phase_increment).rem_euclid(std::f32::consts::TAU);
+                    tone.right_phase = if self.stereo_detune == 0f32 {
+                        tone.phase
+                    } else {
+                        (tone.right_phase + phase_increment * stereo_ratio)
+                            .rem_euclid(std::f32::consts::TAU)
+                    };
+                    tone.advance_glide();
+                    // moving the voice's envelope timestamps along with the clock stops its
+                    // envelope, while the note-on keeps its place among the other voices
+                    if tone.frozen {
+                        tone.frozen_frames += 1;
+                        tone.time_released = tone.time_released.map(|released| released + 1);
+                        tone.choked_at = tone.choked_at.map(|choked| choked + 1);
+                    }
                 } else {
-                    finished_tones.push(note.clone());
+                    self.finished_tones.push(*voice);
+                }
+            }
+            let mut finished_tones = std::mem::take(&mut self.finished_tones);
+            for voice in finished_tones.drain(..) {
+                self.remove_voice(voice);
+            }
+            self.finished_tones = finished_tones;
+            if self.release_noise > 0f32 {
+                let noise = self.rng.next_bipolar() * self.release_noise * gain;
+                left_value += noise as Mix;
+                right_value += noise as Mix;
+                self.release_noise *= release_noise_decay;
+                if self.release_noise < SILENCE_THRESHOLD {
+                    self.release_noise = 0f32;
+                }
+            }
+            self.master_phase =
+                (self.master_phase + master_phase_increment).rem_euclid(std::f32::consts::TAU);
+            advance_lfo(
+                &mut self.lfo_phase,
+                &mut self.lfo_held,
+                lfo_phase_increment,
+                &mut self.lfo_rng,
+            );
+            let (left_value, right_value) = (left_value as f32, right_value as f32);
+            let (left_value, right_value) =
+                self.decorrelator.process(left_value, right_value, self.decorrelation);
+            let follower = self.follower.process(
+                left_input[frame_offset],
+                right_input[frame_offset],
+                follower_attack,
+                follower_release,
+            );
+            let duck = 1f32 - self.follower_duck * follower.min(1f32);
+            let (left_value, right_value) = (left_value * duck, right_value * duck);
+            let (left_value, right_value) = if self.input_mix == 0f32 {
+                (left_value, right_value)
+            } else {
+                (
+                    left_value + left_input[frame_offset] * self.input_mix,
+                    right_value + right_input[frame_offset] * self.input_mix,
+                )
+            };
+            let (left_delayed, right_delayed) = self.delay.process(
+                left_value,
+                right_value,
+                delay_frames,
+                self.delay_feedback,
+                self.ping_pong,
+            );
+            let left_value = left_value * (1f32 - self.delay_mix) + left_delayed * self.delay_mix;
+            let right_value =
+                right_value * (1f32 - self.delay_mix) + right_delayed * self.delay_mix;
+            let (left_reverb, right_reverb) =
+                self.reverb.process(left_value, right_value, self.reverb_size, self.reverb_damp);
+            let left_value = left_value * (1f32 - self.reverb_mix) + left_reverb * self.reverb_mix;
+            let right_value =
+                right_value * (1f32 - self.reverb_mix) + right_reverb * self.reverb_mix;
+            let peak = left_value.abs().max(right_value.abs());
+            let tails = left_delayed.abs().max(right_delayed.abs());
+            let tails = tails.max(left_reverb.abs()).max(right_reverb.abs());
+            if peak.max(tails) < SILENCE_THRESHOLD {
+                self.quiet_frames = self.quiet_frames.saturating_add(1);
+            } else {
+                self.quiet_frames = 0;
+            }
+            let (left_value, right_value) = if self.gate_threshold > GATE_OFF_THRESHOLD {
+                let gate =
+                    self.gate.process(left_value, right_value, gate_threshold, gate_release_step);
+                (left_value * gate, right_value * gate)
+            } else {
+                (left_value, right_value)
+            };
+            let (left_tilted, right_tilted) = self.tilt_filter.process(
+                left_value,
+                right_value,
+                tilt_coefficient,
+                tilt_low,
+                tilt_high,
+            );
+            let (left_value, right_value) = if self.tilt == 0f32 {
+                (left_value, right_value)
+            } else {
+                (left_tilted, right_tilted)
+            };
+            let (left_value, right_value) = if self.soft_clip {
+                (
+                    saturate(left_value, self.knee, self.clip_symmetry),
+                    saturate(right_value, self.knee, self.clip_symmetry),
+                )
+            } else {
+                (left_value, right_value)
+            };
+            let (left_value, right_value) =
+                self.highpass.process(left_value, right_value, hpf_coefficient);
+            let left_value = left_value * self.left_gain_smoother.next(left_gain);
+            let right_value = right_value * self.right_gain_smoother.next(right_gain);
+            let (left_value, right_value) = match self.output_mode {
+                OutputMode::Stereo => (left_value, right_value),
+                OutputMode::Mono => {
+                    let mono = (left_value + right_value) * mono_fold_gain;
+                    (mono, mono)
                 }
+            };
+            *left_out_frame = left_value * left_polarity;
+            *right_out_frame = right_value * right_polarity;
+            if self.raw_osc {
+                *left_out_frame = left_raw as f32;
+                *right_out_frame = right_raw as f32;
+            }
+            // the voices keep running underneath, so no note hangs when the tuner is turned off
+            if self.tuner_tone {
+                let tuner = self.tuner_phase.sin() * gain;
+                *left_out_frame = tuner;
+                *right_out_frame = tuner;
+                self.tuner_phase =
+                    (self.tuner_phase + tuner_phase_increment).rem_euclid(std::f32::consts::TAU);
+            }
+            if self.metronome_enabled {
+                let click = self.metronome.process(beat_increment, self.samplerate);
+                *left_out_frame += click;
+                *right_out_frame += click;
+            }
+            let previous_mute = self.mute_smoother.value;
+            let mute = self.mute_smoother.next(mute_gain);
+            // the smoother only approaches its target, and just below 1 its steps round away
+            // before it gets there, so it is snapped once inaudibly close or stuck
+            if (mute - mute_gain).abs() < SILENCE_THRESHOLD || mute == previous_mute {
+                self.mute_smoother.value = mute_gain;
             }
-            for note in finished_tones {
-                self.active_tones.remove(&note);
+            *left_out_frame *= mute;
+            *right_out_frame *= mute;
+            // catches a feature that lets a NaN, an infinity or a lasting offset through to the
+            // outputs; release builds skip the check
+            debug_assert!(
+                left_out_frame.is_finite() && right_out_frame.is_finite(),
+                "non-finite output at frame {}",
+                frame_index
+            );
+            #[cfg(debug_assertions)]
+            {
+                let (left_dc, right_dc) = &mut self.output_dc;
+                *left_dc += (*left_out_frame - *left_dc) * dc_coefficient;
+                *right_dc += (*right_out_frame - *right_dc) * dc_coefficient;
+                debug_assert!(
+                    left_dc.abs() < DC_GUARD_LIMIT && right_dc.abs() < DC_GUARD_LIMIT,
+                    "output dc of {} and {} at frame {}",
+                    left_dc,
+                    right_dc,
+                    frame_index
+                );
             }
-            *left_out_frame = value;
-            *right_out_frame = value;
-            frame_index += 1;
+            let clipped = left_out_frame.abs() > 1f32 || right_out_frame.abs() > 1f32;
+            self.clip_indicator.process(clipped, 1u32, self.samplerate);
+        }
+        self.current_frame += left_output.len() as u64;
+    }
+}
+
+impl Plugin for Dsfsynth {
+    type Ports = Ports;
+
+    type InitFeatures = Features<'static>;
+    type AudioFeatures = ();
+
+    fn new(plugin_info: &PluginInfo, features: &mut Features<'static>) -> Option<Self> {
+        let samplerate = plugin_info.sample_rate() as f32;
+        let (left_pan, right_pan) = unity_pan_gains(0f32, PanLaw::Minus3Db);
+        Some(Self {
+            adsr: Adsr {
+                attack: -6f32.exp(),
+                decay: -6f32.exp(),
+                sustain: 64f32 / 127f32,
+                release: -6f32.exp(),
+                decay_curve: 0f32,
+                release_curve: 0f32,
+                end_level: 0f32,
+            },
+            brightness: 64f32 / 127f32,
+            gain: -20f32,
+            base_note: 69u8,
+            input_channel: Channel::Ch1,
+            portamento_time: midi_val_to_time(64f32),
+            portamento_mode: PortamentoMode::Off,
+            glide_direction: GlideDirection::Both,
+            last_voice: None,
+            last_pitch: None,
+            min_note: 0u8,
+            max_note: 127u8,
+            fold_notes: false,
+            left_trim: 0f32,
+            right_trim: 0f32,
+            left_gain_smoother: Smoother::new(left_pan, SMOOTHING_TIME, samplerate),
+            right_gain_smoother: Smoother::new(right_pan, SMOOTHING_TIME, samplerate),
+            reset_phase_on_trigger: true,
+            phase_align: false,
+            master_phase: 0f32,
+            offline_quality: OfflineQuality::Off,
+            oversampling: 1u32,
+            pan: 0f32,
+            pan_law: PanLaw::Minus3Db,
+            amp_keytrack: 0f32,
+            humanize: 0f32,
+            stereo_detune: 0f32,
+            dsf_variant: DsfVariant::OneSided,
+            variant_fade: VariantFade::new(DsfVariant::OneSided),
+            filter_cutoff: FILTER_MAX_CUTOFF,
+            filter_resonance: 0f32,
+            filter_keytrack: 0f32,
+            invert_left: false,
+            invert_right: false,
+            env_mode: EnvelopeMode::Gate,
+            tuning_hz: 440f32,
+            pitch_table: Box::new([0f32; 128]),
+            pitch_table_set: false,
+            tuner_tone: false,
+            tuner_phase: 0f32,
+            raw_osc: false,
+            mod_wheel: 0f32,
+            expression: 1f32,
+            pitch_bend: 0f32,
+            bend_up_range: 2f32,
+            bend_down_range: 2f32,
+            pitch_bend_smoother: Smoother::new(0f32, SMOOTHING_TIME, samplerate),
+            delay: Delay::new(samplerate),
+            delay_time: 0.3,
+            delay_feedback: 0.4,
+            delay_mix: 0f32,
+            ping_pong: false,
+            lfo_rate: 5f32,
+            lfo_depth: 0f32,
+            lfo_phase: 0f32,
+            lfo_shape: LfoShape::Sine,
+            lfo_held: 0f32,
+            lfo_rng: Rng::new(LFO_RNG_SEED),
+            channel_pressure: 0f32,
+            aftertouch_to_vibrato: 0f32,
+            gate: Gate::new(samplerate),
+            follower: EnvelopeFollower::default(),
+            follower_attack: 0.01,
+            follower_release: 0.1,
+            follower_duck: 0f32,
+            gate_threshold: GATE_OFF_THRESHOLD,
+            gate_release: 0.1,
+            click: 0f32,
+            rpn: RPN_NULL,
+            rpn_data: 0u16,
+            fine_tuning: 0f32,
+            coarse_tuning: 0f32,
+            quiet_frames: 0usize,
+            reverb: Reverb::new(samplerate),
+            reverb_size: 0.5,
+            reverb_damp: 0.5,
+            reverb_mix: 0f32,
+            decorrelator: Decorrelator::new(samplerate),
+            decorrelation: 0f32,
+            classic_mono: false,
+            mono_notes: Vec::new(),
+            clip_indicator: ClipIndicator::default(),
+            brightness_decay: 0f32,
+            brightness_comp: false,
+            brightness_smoother: Smoother::new(64f32 / 127f32, SMOOTHING_TIME, samplerate),
+            brightness_slew: BRIGHTNESS_DEFAULT_SLEW,
+            slewed_brightness: 64f32 / 127f32,
+            cc_mapping: (1u8, CcDestination::Off),
+            mapped_cc_value: None,
+            tilt: 0f32,
+            tilt_filter: Tilt::default(),
+            glide_bend: GlideBend::FromBent,
+            strum: 0f32,
+            strum_frame: None,
+            strum_index: 0u32,
+            click_smooth: DEFAULT_CLICK_SMOOTH,
+            pitch_env_amount: 0f32,
+            pitch_env_time: 0.05,
+            input_mix: 0f32,
+            release_counts: true,
+            mute: false,
+            mute_smoother: Smoother::new(1f32, MUTE_TIME, samplerate),
+            settle_pending: true,
+            #[cfg(debug_assertions)]
+            output_dc: (0f32, 0f32),
+            host_bpm: 120f32,
+            lfo_sync: false,
+            lfo_division: LfoDivision::Quarter,
+            omni: false,
+            channel_gains: [0f32; 4],
+            lfo_retrigger: false,
+            hpf_cutoff: 20f32,
+            highpass: Highpass::default(),
+            max_noteons_per_block: 256u32,
+            block_noteons: 0u32,
+            velocity_floor: 0f32,
+            low_velocity_boost: 0f32,
+            high_velocity_boost: 0f32,
+            velocity_humanize: 0f32,
+            choke_groups: [None; 2],
+            soft_clip: false,
+            knee: 0.5,
+            clip_symmetry: 0f32,
+            metronome_enabled: false,
+            arp_enabled: false,
+            arp_rate: 4f32,
+            gate_length: 0.5,
+            arpeggiator: Arpeggiator {
+                keys: Vec::with_capacity(128),
+                ..Arpeggiator::default()
+            },
+            host_speed: 0f32,
+            metronome: Metronome::default(),
+            release_layer: 0f32,
+            release_noise: 0f32,
+            freeze: false,
+            sustain_pedal: 0f32,
+            half_pedal: false,
+            hold_last: false,
+            held_voice: None,
+            #[cfg(feature = "surround")]
+            front_rear_gains: (1f32, 0f32),
+            sub_level: 0f32,
+            sub_pan: 0f32,
+            noise_level: 0f32,
+            noise_pan: 0f32,
+            auto_glide: false,
+            glide_octave_snap: false,
+            velocity_to_sustain: 0f32,
+            velocity_to_pan: 0f32,
+            velocity_to_glide: 0f32,
+            haas: 0f32,
+            cpu_throttle: false,
+            cpu_budget: 256f32,
+            unison: 1usize,
+            unison_detune: 10f32,
+            unison_phase_spread: 0f32,
+            detune_distribution: DetuneDistribution::Even,
+            output_mode: OutputMode::Stereo,
+            mono_fold: MonoFold::Average,
+            self_osc: false,
+            rng: Rng::new(RNG_SEED),
+            #[cfg(feature = "debug-automation")]
+            automation: AutomationLog::new(),
+            urids: features.map.populate_collection()?,
+            samplerate,
+            active_tones: HashMap::new(),
+            note_voices: HashMap::new(),
+            tone_pool: Vec::new(),
+            finished_tones: Vec::new(),
+            events: Vec::with_capacity(EVENT_CAPACITY),
+            next_voice_id: 0u64,
+            stack_same_note: false,
+            max_voices: 128usize,
+            steal_priority: StealPriority::Oldest,
+            current_frame: 0u64,
+        })
+    }
+
+    fn run(&mut self, ports: &mut Ports, _: &mut (), sample_count: u32) {
+        #[cfg(feature = "debug-automation")]
+        self.automation.record(&control_values(ports));
+        self.adsr = midi_vals_to_adsr(
+            *(ports.attack),
+            *(ports.decay),
+            *(ports.sustain),
+            *(ports.release),
+            *(ports.min_time),
+            *(ports.max_time),
+        );
+        self.adsr.decay_curve = *(ports.decay_curve);
+        self.adsr.release_curve = *(ports.release_curve);
+        // a single knob scaling every segment, with the release optionally tied to the attack
+        let env_time = *(ports.env_time);
+        let env_ratio = *(ports.env_ratio);
+        if env_ratio > 0f32 {
+            self.adsr.release = self.adsr.attack * env_ratio;
+        }
+        self.adsr.attack *= env_time;
+        self.adsr.decay *= env_time;
+        self.adsr.release *= env_time;
+        self.adsr.end_level = decibel(*(ports.end_threshold));
+        **ports.attack_seconds = self.adsr.attack;
+        **ports.decay_seconds = self.adsr.decay;
+        **ports.release_seconds = self.adsr.release;
+        self.brightness = midi_val_to_ratio(*(ports.brightness));
+        self.gain = *(ports.gain);
+        self.base_note = *(ports.base_note) as u8;
+        self.input_channel =
+            wmidi::Channel::from_index(*(ports.input_channel) as u8 - 1u8).unwrap();
+        self.portamento_time = midi_val_to_time(*(ports.portamento_time));
+        self.portamento_mode = port_to_portamento_mode(*(ports.portamento_mode));
+        self.glide_direction = port_to_glide_direction(*(ports.glide_direction));
+        self.min_note = *(ports.min_note) as u8;
+        self.max_note = *(ports.max_note) as u8;
+        self.fold_notes = *(ports.fold_notes) > 0.5;
+        self.left_trim = *(ports.left_trim);
+        self.right_trim = *(ports.right_trim);
+        self.reset_phase_on_trigger = *(ports.reset_phase_on_trigger) > 0.5;
+        self.phase_align = *(ports.phase_align) > 0.5;
+        self.offline_quality = port_to_offline_quality(*(ports.offline_quality));
+        self.pan = *(ports.pan);
+        self.pan_law = port_to_pan_law(*(ports.pan_law));
+        self.amp_keytrack = *(ports.amp_keytrack);
+        self.humanize = *(ports.humanize) / 100f32;
+        self.stereo_detune = *(ports.stereo_detune);
+        let dsf_variant = port_to_dsf_variant(*(ports.dsf_variant));
+        if dsf_variant != self.dsf_variant {
+            self.variant_fade = VariantFade {
+                from: self.dsf_variant,
+                to: dsf_variant,
+                progress: 0f32,
+            };
+            self.dsf_variant = dsf_variant;
+        }
+        self.filter_cutoff = *(ports.filter_cutoff);
+        self.filter_resonance = *(ports.filter_resonance);
+        self.filter_keytrack = *(ports.filter_keytrack);
+        self.invert_left = *(ports.invert_left) > 0.5;
+        self.invert_right = *(ports.invert_right) > 0.5;
+        self.env_mode = port_to_envelope_mode(*(ports.env_mode));
+        self.tuning_hz = *(ports.tuning_hz);
+        self.tuner_tone = *(ports.tuner_tone) > 0.5;
+        self.raw_osc = *(ports.raw_osc) > 0.5;
+        self.stack_same_note = *(ports.stack_same_note) > 0.5;
+        self.bend_up_range = *(ports.bend_up_range);
+        self.bend_down_range = *(ports.bend_down_range);
+        self.max_voices = (*(ports.max_voices) as usize).max(1);
+        self.steal_priority = port_to_steal_priority(*(ports.steal_priority));
+        self.delay_time = *(ports.delay_time) / 1000f32;
+        self.delay_feedback = *(ports.delay_feedback);
+        self.delay_mix = *(ports.delay_mix);
+        self.ping_pong = *(ports.ping_pong) > 0.5;
+        self.lfo_rate = *(ports.lfo_rate);
+        self.lfo_depth = *(ports.lfo_depth);
+        self.aftertouch_to_vibrato = *(ports.aftertouch_to_vibrato);
+        self.gate_threshold = *(ports.gate_threshold);
+        self.gate_release = *(ports.gate_release) / 1000f32;
+        self.follower_attack = *(ports.follower_attack) / 1000f32;
+        self.follower_release = *(ports.follower_release) / 1000f32;
+        self.follower_duck = *(ports.follower_duck);
+        self.click = *(ports.click);
+        self.reverb_size = *(ports.reverb_size);
+        self.reverb_damp = *(ports.reverb_damp);
+        self.reverb_mix = *(ports.reverb_mix);
+        self.decorrelation = *(ports.decorrelation);
+        self.brightness_decay = *(ports.brightness_decay);
+        self.brightness_slew = *(ports.brightness_slew);
+        self.brightness_comp = *(ports.brightness_comp) > 0.5;
+        self.tilt = *(ports.tilt);
+        self.glide_bend = port_to_glide_bend(*(ports.glide_bend));
+        self.strum = *(ports.strum) / 1000f32;
+        self.click_smooth = *(ports.click_smooth) / 1000f32;
+        self.pitch_env_amount = *(ports.pitch_env_amount);
+        self.pitch_env_time = *(ports.pitch_env_time) / 1000f32;
+        self.input_mix = *(ports.input_mix);
+        self.release_counts = *(ports.release_counts) > 0.5;
+        self.mute = *(ports.mute) > 0.5;
+        self.host_bpm = *(ports.host_bpm);
+        self.lfo_sync = *(ports.lfo_sync) > 0.5;
+        self.lfo_division = port_to_lfo_division(*(ports.lfo_division));
+        self.lfo_shape = port_to_lfo_shape(*(ports.lfo_shape));
+        self.omni = *(ports.omni) > 0.5;
+        self.lfo_retrigger = *(ports.lfo_retrigger) > 0.5;
+        self.hpf_cutoff = *(ports.hpf_cutoff);
+        self.max_noteons_per_block = *(ports.max_noteons_per_block) as u32;
+        self.block_noteons = 0u32;
+        self.velocity_floor = *(ports.velocity_floor);
+        self.low_velocity_boost = *(ports.low_velocity_boost);
+        self.high_velocity_boost = *(ports.high_velocity_boost);
+        self.velocity_humanize = *(ports.velocity_humanize);
+        self.choke_groups = [
+            (*(ports.choke_1) > 0.5)
+                .then_some((*(ports.choke_1_low) as u8, *(ports.choke_1_high) as u8)),
+            (*(ports.choke_2) > 0.5)
+                .then_some((*(ports.choke_2_low) as u8, *(ports.choke_2_high) as u8)),
+        ];
+        self.soft_clip = *(ports.soft_clip) > 0.5;
+        self.knee = *(ports.knee);
+        self.clip_symmetry = *(ports.clip_symmetry);
+        self.metronome_enabled = *(ports.metronome) > 0.5;
+        let arp_enabled = *(ports.arp) > 0.5;
+        if self.arp_enabled && !arp_enabled {
+            self.stop_arpeggiator();
+        }
+        self.arp_enabled = arp_enabled;
+        self.arp_rate = (*(ports.arp_rate)).max(1f32);
+        self.gate_length = *(ports.gate_length);
+        self.metronome.position = *(ports.host_bar_beat) as f64;
+        self.host_speed = *(ports.host_speed);
+        self.release_layer = *(ports.release_layer);
+        self.sub_level = *(ports.sub_level);
+        self.sub_pan = *(ports.sub_pan);
+        self.noise_level = *(ports.noise_level);
+        self.noise_pan = *(ports.noise_pan);
+        self.auto_glide = *(ports.auto_glide) > 0.5;
+        self.glide_octave_snap = *(ports.glide_octave_snap) > 0.5;
+        self.velocity_to_sustain = *(ports.velocity_to_sustain);
+        self.velocity_to_pan = *(ports.velocity_to_pan);
+        self.velocity_to_glide = *(ports.velocity_to_glide);
+        self.haas = *(ports.haas);
+        self.cpu_throttle = *(ports.cpu_throttle) > 0.5;
+        self.cpu_budget = *(ports.cpu_budget);
+        self.unison = (*(ports.unison) as usize).max(1);
+        self.unison_detune = *(ports.unison_detune);
+        self.unison_phase_spread = *(ports.unison_phase_spread);
+        self.detune_distribution = port_to_detune_distribution(*(ports.detune_distribution));
+        self.output_mode = port_to_output_mode(*(ports.output_mode));
+        self.mono_fold = port_to_mono_fold(*(ports.mono_fold));
+        self.self_osc = *(ports.self_osc) > 0.5;
+        self.half_pedal = *(ports.half_pedal) > 0.5;
+        self.hold_last = *(ports.hold_last) > 0.5;
+        if !self.hold_last {
+            self.release_held_voice();
+        }
+        let freeze = *(ports.freeze) > 0.5;
+        if freeze != self.freeze {
+            self.set_freeze(freeze);
         }
-        self.current_frame += sample_count;
+        self.channel_gains = [
+            *(ports.channel_1_gain),
+            *(ports.channel_2_gain),
+            *(ports.channel_3_gain),
+            *(ports.channel_4_gain),
+        ];
+        let classic_mono = *(ports.classic_mono) > 0.5;
+        if classic_mono != self.classic_mono {
+            self.mono_notes.clear();
+        }
+        self.classic_mono = classic_mono;
+        let cc_mapping = (
+            *(ports.cc_source) as u8,
+            port_to_cc_destination(*(ports.cc_dest)),
+        );
+        if cc_mapping != self.cc_mapping {
+            self.mapped_cc_value = None;
+        }
+        self.cc_mapping = cc_mapping;
+        self.apply_cc_mapping();
+        self.oversampling = match self.offline_quality {
+            OfflineQuality::Always => OFFLINE_OVERSAMPLING,
+            OfflineQuality::FreeWheeling if *(ports.free_wheeling) > 0.5 => OFFLINE_OVERSAMPLING,
+            _ => 1u32,
+        };
+
+        let input_sequence = ports
+            .midi_input
+            .read(self.urids.atom.sequence, self.urids.unit.beat)
+            .unwrap();
+
+        // taken out for the block so that handling the events can borrow the synth
+        let mut events = std::mem::take(&mut self.events);
+        events.clear();
+        for (timestamp, atom) in input_sequence {
+            let message = if let Some(message) = atom
+                .read(self.urids.midi.wmidi, ())
+                .map(|message| message.to_owned())
+            {
+                message
+            } else {
+                continue;
+            };
+
+            let previous = events.last().map_or(0usize, |(frame, _)| *frame);
+            let frame = timestamp
+                .as_frames()
+                .unwrap_or(0)
+                .clamp(previous as i64, sample_count as i64) as usize;
+            events.push((frame, message));
+        }
+        if self.strum > 0f32 {
+            sort_chords(&mut events);
+        }
+
+        // events are handled at their frame within the block, rendering up to each of them
+        let mut rendered = 0usize;
+        for (frame, message) in events.drain(..) {
+            self.render_until(ports, &mut rendered, frame);
+            self.handle_message(message);
+        }
+        self.events = events;
+        self.render_until(ports, &mut rendered, sample_count as usize);
+        #[cfg(feature = "surround")]
+        self.place_front_back(ports);
+        **ports.clip = self.clip_indicator.level;
+        // mirrors the derived values, in the units of their input ports
+        **ports.effective_brightness = self.effective_brightness() * 127f32;
+        **ports.effective_gain = self.effective_gain();
+        **ports.bpm = self.host_bpm;
+        **ports.correlation = correlation(&ports.left_audio_output, &ports.right_audio_output);
+        **ports.follower = self.follower.level;
     }
 
+    // activating resets what was playing: voices, held keys and pedals, controller state and
+    // the effect tails all start out empty, as after instantiation. the smoothed parameters
+    // are not reset to fixed values but settle at the targets of the first block that runs,
+    // so that it neither fades in from silence nor glides from settings that were changed
+    // while the plugin was inactive
     fn activate(&mut self, _features: &mut Features<'static>) {
-        self.active_tones = HashMap::new();
-        self.current_frame = 0u32;
+        self.clear_voices();
+        self.current_frame = 0u64;
+        self.master_phase = 0f32;
+        self.last_voice = None;
+        self.last_pitch = None;
+        self.sustain_pedal = 0f32;
+        self.held_voice = None;
+        self.rng = Rng::new(RNG_SEED);
+        self.pitch_bend = 0f32;
+        self.mod_wheel = 0f32;
+        self.expression = 1f32;
+        self.delay.clear();
+        self.gate = Gate::new(self.samplerate);
+        self.follower = EnvelopeFollower::default();
+        self.lfo_phase = 0f32;
+        self.lfo_held = 0f32;
+        self.lfo_rng = Rng::new(LFO_RNG_SEED);
+        self.channel_pressure = 0f32;
+        self.rpn = RPN_NULL;
+        self.rpn_data = 0u16;
+        self.fine_tuning = 0f32;
+        self.coarse_tuning = 0f32;
+        self.quiet_frames = 0usize;
+        self.reverb.clear();
+        self.decorrelator.clear();
+        self.mono_notes.clear();
+        self.clip_indicator = ClipIndicator::default();
+        self.mapped_cc_value = None;
+        self.tilt_filter = Tilt::default();
+        self.strum_frame = None;
+        self.highpass = Highpass::default();
+        self.settle_pending = true;
+        #[cfg(debug_assertions)]
+        {
+            self.output_dc = (0f32, 0f32);
+        }
+        self.metronome = Metronome::default();
+        self.arpeggiator.keys.clear();
+        self.arpeggiator.position = 0usize;
+        self.arpeggiator.next_step = None;
+        self.arpeggiator.sounding = None;
+        self.release_noise = 0f32;
     }
 
     fn deactivate(&mut self, _features: &mut Features<'static>) {
-        self.active_tones = HashMap::new();
-        self.current_frame = 0u32;
+        self.clear_voices();
+        self.mono_notes.clear();
+        self.current_frame = 0u64;
+    }
+
+    fn extension_data(uri: &Uri) -> Option<&'static dyn std::any::Any> {
+        match_extensions!(uri, StateDescriptor<Self>)
+    }
+}
+
+// the state holds the pitch table while one is set, as a vector of 128 frequencies in Hz;
+// the control ports are saved by the host
+impl State for Dsfsynth {
+    type StateFeatures = ();
+
+    fn save(&self, mut store: StoreHandle, _features: ()) -> Result<(), StateErr> {
+        if self.pitch_table_set {
+            store
+                .draft(self.urids.pitch_table)
+                .init(self.urids.atom.vector(), self.urids.atom.float)?
+                .append(&self.pitch_table[..]);
+        }
+        store.commit_all()
+    }
+
+    fn restore(&mut self, store: RetrieveHandle, _features: ()) -> Result<(), StateErr> {
+        let frequencies = match store.retrieve(self.urids.pitch_table) {
+            Ok(property) => property.read(self.urids.atom.vector(), self.urids.atom.float)?,
+            // a state without a table goes back to the tuning formula; lv2_state reports a
+            // missing property as unknown when the host leaves its type at 0
+            Err(StateErr::NoProperty | StateErr::Unknown) => {
+                self.set_pitch_table(None);
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+        let table = <[f32; 128]>::try_from(frequencies).map_err(|_| StateErr::BadData)?;
+        self.set_pitch_table(Some(table));
+        Ok(())
     }
 }
 
 lv2_descriptors!(Dsfsynth);
+
+#[cfg(test)]
+mod tests;
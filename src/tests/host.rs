@@ -0,0 +1,416 @@
+// a minimal lv2 host: it instantiates the plugin through lv2_descriptor as any host would,
+// connects every port to its own buffer, sets the control ports to their defaults from the
+// ttl and runs blocks with the midi events queued for them
+use super::super::{lv2_descriptor, Dsfsynth};
+use lv2::lv2_state::Storage;
+use lv2::prelude::{PluginInstance, LV2_Descriptor};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+
+pub const SAMPLERATE: f32 = 48000f32;
+// longest block the buffers are sized for
+pub const MAX_BLOCK: usize = 8192;
+const MIDI_CAPACITY: usize = 4096;
+
+const URID_MAP_URI: &[u8] = b"http://lv2plug.in/ns/ext/urid#map\0";
+const ATOM_SEQUENCE_URI: &str = "http://lv2plug.in/ns/ext/atom#Sequence";
+const MIDI_EVENT_URI: &str = "http://lv2plug.in/ns/ext/midi#MidiEvent";
+const STATE_INTERFACE_URI: &[u8] = b"http://lv2plug.in/ns/ext/state#interface\0";
+const STATE_IS_POD: u32 = 1;
+
+// the two host structures lv2_core keeps to itself
+#[repr(C)]
+struct Feature {
+    uri: *const c_char,
+    data: *mut c_void,
+}
+
+#[repr(C)]
+struct UridMap {
+    handle: *mut c_void,
+    map: unsafe extern "C" fn(*mut c_void, *const c_char) -> u32,
+}
+
+type StoreFunction =
+    Option<unsafe extern "C" fn(*mut c_void, u32, *const c_void, usize, u32, u32) -> u32>;
+type RetrieveFunction =
+    Option<unsafe extern "C" fn(*mut c_void, u32, *mut usize, *mut u32, *mut u32) -> *const c_void>;
+
+// and the state interface, of which only the two function pointers are needed
+#[repr(C)]
+struct StateInterface {
+    save: unsafe extern "C" fn(*mut c_void, StoreFunction, *mut c_void, u32, *const *const Feature)
+        -> u32,
+    restore:
+        unsafe extern "C" fn(*mut c_void, RetrieveFunction, *mut c_void, u32, *const *const Feature)
+            -> u32,
+}
+
+#[derive(Default)]
+struct Uris {
+    uris: RefCell<Vec<CString>>,
+}
+
+unsafe extern "C" fn map_uri(handle: *mut c_void, uri: *const c_char) -> u32 {
+    let uris = &*(handle as *const Uris);
+    uris.map(CStr::from_ptr(uri))
+}
+
+impl Uris {
+    fn map(&self, uri: &CStr) -> u32 {
+        let mut uris = self.uris.borrow_mut();
+        if let Some(index) = uris.iter().position(|known| known.as_c_str() == uri) {
+            return index as u32 + 1;
+        }
+        uris.push(uri.to_owned());
+        uris.len() as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PortType {
+    Control,
+    Audio,
+    Atom,
+}
+
+// names and types of the fields of Ports in declaration order, which is the port order
+fn port_fields() -> Vec<(String, PortType)> {
+    let source = include_str!("../lib.rs");
+    let start = source.find("pub struct Ports {").unwrap();
+    let end = start + source[start..].find("\n}").unwrap();
+    let mut fields = vec![];
+    let mut skip = false;
+    for line in source[start..end].lines().skip(1) {
+        let line = line.trim();
+        if line.starts_with("#[cfg(feature = \"surround\")]") {
+            skip = !cfg!(feature = "surround");
+            continue;
+        }
+        if let Some((name, port_type)) = line.split_once(':') {
+            if !line.starts_with("//") {
+                let port_type = if port_type.contains("Audio") {
+                    PortType::Audio
+                } else if port_type.contains("AtomPort") {
+                    PortType::Atom
+                } else {
+                    PortType::Control
+                };
+                if !skip {
+                    fields.push((name.trim().to_string(), port_type));
+                }
+                skip = false;
+            }
+        }
+    }
+    fields
+}
+
+// lv2:default, lv2:minimum and lv2:maximum of each port index described in the ttl files
+fn port_properties() -> HashMap<usize, PortProperties> {
+    let mut ttl = include_str!("../../eg-dsf_synth-rs.lv2/dsf_synth.ttl").to_string();
+    if cfg!(feature = "surround") {
+        ttl.push_str(include_str!("../../eg-dsf_synth-rs.lv2/surround.ttl"));
+    }
+    let mut properties = HashMap::<usize, PortProperties>::new();
+    let mut index = None;
+    for line in ttl.lines() {
+        let line = line.trim().trim_end_matches(';').trim();
+        if let Some(value) = line.strip_prefix("lv2:index ") {
+            index = value.parse::<usize>().ok();
+            continue;
+        }
+        let Some(index) = index else { continue };
+        let entry = properties.entry(index).or_default();
+        if let Some(value) = line.strip_prefix("lv2:default ") {
+            entry.default = value.parse::<f32>().unwrap();
+        } else if let Some(value) = line.strip_prefix("lv2:minimum ") {
+            entry.minimum = value.parse::<f32>().unwrap();
+        } else if let Some(value) = line.strip_prefix("lv2:maximum ") {
+            entry.maximum = value.parse::<f32>().unwrap();
+        }
+    }
+    properties
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PortProperties {
+    default: f32,
+    minimum: f32,
+    maximum: f32,
+}
+
+pub struct Host {
+    descriptor: &'static LV2_Descriptor,
+    handle: *mut c_void,
+    fields: Vec<(String, PortType)>,
+    properties: HashMap<usize, PortProperties>,
+    // one value per port index, so that every control port has a stable address
+    controls: Box<[f32]>,
+    audio: Vec<Box<[f32]>>,
+    midi: Box<[u64]>,
+    events: Vec<(u32, Vec<u8>)>,
+    sequence_urid: u32,
+    midi_urid: u32,
+    _map: Box<UridMap>,
+    _uris: Box<Uris>,
+}
+
+impl Host {
+    pub fn new() -> Self {
+        let descriptor = unsafe { &*lv2_descriptor(0) };
+        let uris = Box::<Uris>::default();
+        let mut map = Box::new(UridMap {
+            handle: &*uris as *const Uris as *mut c_void,
+            map: map_uri,
+        });
+        let feature = Feature {
+            uri: URID_MAP_URI.as_ptr() as *const c_char,
+            data: &mut *map as *mut UridMap as *mut c_void,
+        };
+        let features = [&feature as *const Feature, std::ptr::null()];
+        let bundle = CString::new("/tmp/eg-dsf_synth-rs.lv2/").unwrap();
+        let handle = unsafe {
+            (descriptor.instantiate.unwrap())(
+                descriptor,
+                SAMPLERATE as f64,
+                bundle.as_ptr(),
+                features.as_ptr() as *const *const _,
+            )
+        };
+        assert!(!handle.is_null());
+        let fields = port_fields();
+        let properties = port_properties();
+        let controls = (0..fields.len())
+            .map(|index| properties.get(&index).map_or(0f32, |port| port.default))
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+        let sequence_urid = uris.map(&CString::new(ATOM_SEQUENCE_URI).unwrap());
+        let midi_urid = uris.map(&CString::new(MIDI_EVENT_URI).unwrap());
+        let mut host = Self {
+            descriptor,
+            handle,
+            fields,
+            properties,
+            controls,
+            audio: vec![],
+            midi: vec![0u64; MIDI_CAPACITY].into_boxed_slice(),
+            events: vec![],
+            sequence_urid,
+            midi_urid,
+            _map: map,
+            _uris: uris,
+        };
+        host.connect();
+        unsafe { (host.descriptor.activate.unwrap())(host.handle) };
+        host
+    }
+
+    fn connect(&mut self) {
+        let connect = self.descriptor.connect_port.unwrap();
+        for (index, (_, port_type)) in self.fields.iter().enumerate() {
+            let data = match port_type {
+                PortType::Control => &mut self.controls[index] as *mut f32 as *mut c_void,
+                PortType::Audio => {
+                    self.audio.push(vec![0f32; MAX_BLOCK].into_boxed_slice());
+                    self.audio.last_mut().unwrap().as_mut_ptr() as *mut c_void
+                }
+                PortType::Atom => self.midi.as_mut_ptr() as *mut c_void,
+            };
+            unsafe { connect(self.handle, index as u32, data) };
+        }
+    }
+
+    fn index(&self, name: &str) -> usize {
+        self.fields
+            .iter()
+            .position(|(field, _)| field == name)
+            .unwrap_or_else(|| panic!("no port {}", name))
+    }
+
+    fn audio_slot(&self, name: &str) -> usize {
+        let index = self.index(name);
+        self.fields[..index].iter().filter(|(_, port_type)| *port_type == PortType::Audio).count()
+    }
+
+    // sets a control input port
+    pub fn set(&mut self, name: &str, value: f32) -> &mut Self {
+        let index = self.index(name);
+        self.controls[index] = value;
+        self
+    }
+
+    // reads back a control port, such as an output written by the last block
+    pub fn get(&self, name: &str) -> f32 {
+        self.controls[self.index(name)]
+    }
+
+    pub fn cc(&mut self, frame: u32, controller: u8, value: u8) -> &mut Self {
+        self.midi(frame, &[0xb0, controller, value])
+    }
+
+    // sets every control input port to its minimum or to its maximum
+    pub fn set_all_to_extreme(&mut self, maximum: bool) -> &mut Self {
+        for index in 0..self.fields.len() {
+            if let (PortType::Control, Some(port)) =
+                (self.fields[index].1, self.properties.get(&index))
+            {
+                self.controls[index] = if maximum { port.maximum } else { port.minimum };
+            }
+        }
+        self
+    }
+
+    // the plugin instance behind the handle, for state that no port shows
+    pub fn synth(&mut self) -> &mut Dsfsynth {
+        unsafe { (*(self.handle as *mut PluginInstance<Dsfsynth>)).plugin_handle() }
+    }
+
+    // queues a raw midi message for the next block
+    pub fn midi(&mut self, frame: u32, bytes: &[u8]) -> &mut Self {
+        self.events.push((frame, bytes.to_vec()));
+        self
+    }
+
+    pub fn note_on(&mut self, frame: u32, note: u8, velocity: u8) -> &mut Self {
+        self.midi(frame, &[0x90, note, velocity])
+    }
+
+    pub fn note_off(&mut self, frame: u32, note: u8) -> &mut Self {
+        self.midi(frame, &[0x80, note, 64])
+    }
+
+    // writes the queued events to the midi input for the next block
+    pub fn write_sequence(&mut self) {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&0u32.to_ne_bytes());
+        bytes.extend_from_slice(&0u32.to_ne_bytes());
+        self.events.sort_by_key(|(frame, _)| *frame);
+        for (frame, message) in self.events.drain(..) {
+            bytes.extend_from_slice(&(frame as i64).to_ne_bytes());
+            bytes.extend_from_slice(&(message.len() as u32).to_ne_bytes());
+            bytes.extend_from_slice(&self.midi_urid.to_ne_bytes());
+            bytes.extend_from_slice(&message);
+            while bytes.len() % 8 != 0 {
+                bytes.push(0u8);
+            }
+        }
+        let mut atom = vec![];
+        atom.extend_from_slice(&(bytes.len() as u32).to_ne_bytes());
+        atom.extend_from_slice(&self.sequence_urid.to_ne_bytes());
+        atom.extend_from_slice(&bytes);
+        assert!(atom.len() <= self.midi.len() * 8, "midi buffer too small");
+        for (word, chunk) in self.midi.iter_mut().zip(atom.chunks(8)) {
+            let mut padded = [0u8; 8];
+            padded[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_ne_bytes(padded);
+        }
+    }
+
+    // runs one block with the queued events without copying the output, so that the only
+    // work done is the plugin's own
+    pub fn run_in_place(&mut self, frames: usize) {
+        self.write_sequence();
+        self.run_plugin(frames);
+    }
+
+    // runs one block with the events last written to the sequence
+    pub fn run_plugin(&mut self, frames: usize) {
+        assert!(frames <= MAX_BLOCK);
+        unsafe { (self.descriptor.run.unwrap())(self.handle, frames as u32) };
+    }
+
+    // runs one block with the queued events and returns the left and right output
+    pub fn run(&mut self, frames: usize) -> (Vec<f32>, Vec<f32>) {
+        self.run_in_place(frames);
+        (self.output("left_audio_output", frames), self.output("right_audio_output", frames))
+    }
+
+    // runs `frames` in blocks of at most `block` frames, all queued events in the first
+    pub fn render(&mut self, frames: usize, block: usize) -> (Vec<f32>, Vec<f32>) {
+        let (mut left, mut right) = (vec![], vec![]);
+        let mut done = 0usize;
+        while done < frames {
+            let length = block.min(frames - done);
+            let (block_left, block_right) = self.run(length);
+            left.extend(block_left);
+            right.extend(block_right);
+            done += length;
+        }
+        (left, right)
+    }
+
+    // fills an audio input for the following blocks
+    pub fn input(&mut self, name: &str, samples: &[f32]) -> &mut Self {
+        let slot = self.audio_slot(name);
+        self.audio[slot][..samples.len()].copy_from_slice(samples);
+        self
+    }
+
+    pub fn output(&self, name: &str, frames: usize) -> Vec<f32> {
+        self.audio[self.audio_slot(name)][..frames].to_vec()
+    }
+
+    pub fn voices(&mut self) -> usize {
+        self.synth().active_tones.len()
+    }
+
+    fn state_interface(&self) -> &'static StateInterface {
+        let extension_data = self.descriptor.extension_data.unwrap();
+        let interface = unsafe { extension_data(STATE_INTERFACE_URI.as_ptr() as *const c_char) };
+        assert!(!interface.is_null(), "no state interface");
+        unsafe { &*(interface as *const StateInterface) }
+    }
+
+    // saves the plugin state through the state interface, as a host does for a session
+    pub fn save_state(&mut self) -> Storage {
+        let mut storage = Storage::default();
+        let features = [std::ptr::null::<Feature>()];
+        let status = unsafe {
+            (self.state_interface().save)(
+                self.handle,
+                Some(Storage::extern_store),
+                &mut storage as *mut Storage as *mut c_void,
+                STATE_IS_POD,
+                features.as_ptr(),
+            )
+        };
+        assert_eq!(status, 0, "saving the state failed");
+        storage
+    }
+
+    // restores a state saved by save_state, of this or another instance
+    pub fn restore_state(&mut self, storage: &mut Storage) -> &mut Self {
+        let features = [std::ptr::null::<Feature>()];
+        let status = unsafe {
+            (self.state_interface().restore)(
+                self.handle,
+                Some(Storage::extern_retrieve),
+                storage as *mut Storage as *mut c_void,
+                STATE_IS_POD,
+                features.as_ptr(),
+            )
+        };
+        assert_eq!(status, 0, "restoring the state failed");
+        self
+    }
+
+    // deactivates and activates the plugin again, as a host does when it restarts its engine
+    pub fn reactivate(&mut self) -> &mut Self {
+        unsafe {
+            (self.descriptor.deactivate.unwrap())(self.handle);
+            (self.descriptor.activate.unwrap())(self.handle);
+        }
+        self
+    }
+}
+
+impl Drop for Host {
+    fn drop(&mut self) {
+        unsafe {
+            (self.descriptor.deactivate.unwrap())(self.handle);
+            (self.descriptor.cleanup.unwrap())(self.handle);
+        }
+    }
+}
@@ -0,0 +1,2461 @@
+use super::*;
+use host::{Host, SAMPLERATE};
+
+mod allocations;
+mod host;
+mod spectrum;
+
+fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0f32, |peak, sample| peak.max(sample.abs()))
+}
+
+// the most recently pressed voice
+fn newest(host: &mut Host) -> Tone {
+    host.synth().active_tones.values().max_by_key(|tone| tone.time_pressed).unwrap().clone()
+}
+
+#[test]
+fn a_note_sounds_and_releases() {
+    let mut host = Host::new();
+    host.note_on(0, 69, 100);
+    let (left, right) = host.run(4800);
+    assert!(peak(&left) > 0.01 && peak(&right) > 0.01);
+    host.note_off(0, 69);
+    host.render(SAMPLERATE as usize * 2, 512);
+    assert_eq!(host.voices(), 0);
+}
+
+// glides from a held note to `to` and reports whether the new voice glides
+fn fingered_glide(direction: f32, from: u8, to: u8) -> bool {
+    let mut host = Host::new();
+    host.set("portamento_mode", 1f32).set("glide_direction", direction);
+    host.note_on(0, from, 100);
+    host.run(64);
+    host.note_on(0, to, 100);
+    host.run(64);
+    newest(&mut host).glide_frames > 0
+}
+
+#[test]
+fn glide_direction_limits_glides_to_one_direction() {
+    // both
+    assert!(fingered_glide(0f32, 69, 74));
+    assert!(fingered_glide(0f32, 74, 69));
+    // up only
+    assert!(fingered_glide(1f32, 69, 74));
+    assert!(!fingered_glide(1f32, 74, 69));
+    // down only
+    assert!(!fingered_glide(2f32, 69, 74));
+    assert!(fingered_glide(2f32, 74, 69));
+}
+
+// plays notes across the keyboard, some below the base note, and releases them
+fn play_across_keyboard(host: &mut Host) -> (Vec<f32>, Vec<f32>) {
+    for note in [0u8, 21, 60, 68, 69, 127] {
+        host.note_on(0, note, 127);
+    }
+    let (mut left, mut right) = host.render(SAMPLERATE as usize / 2, 256);
+    for note in [0u8, 21, 60, 68, 69, 127] {
+        host.note_off(0, note);
+    }
+    let (left_release, right_release) = host.render(SAMPLERATE as usize / 2, 256);
+    left.extend(left_release);
+    right.extend(right_release);
+    (left, right)
+}
+
+#[test]
+fn full_brightness_and_notes_below_the_base_note_stay_finite() {
+    let mut host = Host::new();
+    host.set("brightness", 127f32);
+    let (left, right) = play_across_keyboard(&mut host);
+    assert!(left.iter().chain(&right).all(|sample| sample.is_finite()));
+}
+
+#[test]
+fn every_control_at_its_extreme_stays_finite() {
+    for maximum in [false, true] {
+        let mut host = Host::new();
+        host.set_all_to_extreme(maximum);
+        let (left, right) = play_across_keyboard(&mut host);
+        assert!(left.iter().chain(&right).all(|sample| sample.is_finite()), "{}", maximum);
+    }
+}
+
+#[test]
+fn highpass_recovers_from_a_non_finite_sample() {
+    let mut highpass = Highpass::default();
+    highpass.process(f32::NAN, f32::INFINITY, 0.999);
+    highpass.process(0.5, 0.5, 0.999);
+    let (left, right) = highpass.process(0.5, 0.5, 0.999);
+    assert!(left.is_finite() && right.is_finite() && left > 0.4 && right > 0.4);
+}
+
+#[test]
+fn output_recovers_after_a_block_at_full_brightness() {
+    let mut host = Host::new();
+    host.set("brightness", 127f32).note_on(0, 69, 100);
+    host.run(512);
+    host.set("brightness", 64f32);
+    let (left, right) = host.render(SAMPLERATE as usize / 4, 512);
+    assert!(left.iter().chain(&right).all(|sample| sample.is_finite()));
+    let tail = left.len() - 4800;
+    assert!(peak(&left[tail..]) > 0.01 && peak(&right[tail..]) > 0.01);
+}
+
+// releases a held note after `change` changed the note range and reports whether its voice
+// went away
+fn releases_after(classic_mono: bool, change: impl Fn(&mut Host)) -> bool {
+    let mut host = Host::new();
+    host.set("classic_mono", classic_mono as u8 as f32).note_on(0, 72, 100);
+    host.run(512);
+    change(&mut host);
+    host.run(512);
+    host.note_off(0, 72);
+    host.render(SAMPLERATE as usize * 2, 512);
+    host.voices() == 0
+}
+
+#[test]
+fn a_note_off_releases_its_note_after_the_note_range_changed() {
+    for classic_mono in [false, true] {
+        assert!(releases_after(classic_mono, |host| {
+            host.set("max_note", 70f32);
+        }));
+        assert!(releases_after(classic_mono, |host| {
+            host.set("min_note", 80f32);
+        }));
+        assert!(releases_after(classic_mono, |host| {
+            host.set("max_note", 70f32).set("fold_notes", 1f32);
+        }));
+    }
+}
+
+#[test]
+fn a_folded_note_releases_after_folding_is_turned_off() {
+    let mut host = Host::new();
+    host.set("max_note", 70f32).set("fold_notes", 1f32);
+    host.note_on(0, 72, 100);
+    host.run(512);
+    assert_eq!(host.voices(), 1);
+    host.set("fold_notes", 0f32);
+    host.run(512);
+    host.note_off(0, 72);
+    host.render(SAMPLERATE as usize * 2, 512);
+    assert_eq!(host.voices(), 0);
+}
+
+// plays a few chords with overlapping notes, twice without counting so that the pool holds as
+// many voices as the chords overlap and every buffer they reuse has grown to its size, and
+// returns the allocations of the third time
+fn allocations_while_playing(host: &mut Host) -> usize {
+    let mut allocations = 0usize;
+    for _ in 0..3 {
+        allocations = 0;
+        for chord in [[60u8, 64, 67], [62, 65, 69], [60, 64, 67], [59, 62, 67]] {
+            for note in chord {
+                host.note_on(0, note, 100).note_on(64, note, 90);
+            }
+            host.write_sequence();
+            allocations += allocations::count(|| host.run_plugin(256));
+            for note in chord {
+                host.note_off(32, note).note_off(128, note);
+            }
+            host.write_sequence();
+            allocations += allocations::count(|| host.run_plugin(256));
+            host.write_sequence();
+            allocations += allocations::count(|| host.run_plugin(4096));
+        }
+    }
+    allocations
+}
+
+#[test]
+fn playing_does_not_allocate() {
+    let mut host = Host::new();
+    assert_eq!(allocations_while_playing(&mut host), 0);
+    let mut host = Host::new();
+    host.set("unison", 4f32).set("haas", 20f32).set("stack_same_note", 1f32);
+    assert_eq!(allocations_while_playing(&mut host), 0);
+}
+
+#[test]
+fn every_pan_law_keeps_the_centre_at_unity() {
+    for pan_law in [PanLaw::ZeroDb, PanLaw::Minus3Db, PanLaw::Minus4_5Db, PanLaw::Minus6Db] {
+        let (left, right) = unity_pan_gains(0f32, pan_law);
+        assert!((left - 1f32).abs() < 1e-6 && (right - 1f32).abs() < 1e-6, "{:?}", pan_law);
+    }
+    let render = |pan_law: f32| {
+        let mut host = Host::new();
+        host.set("pan_law", pan_law).note_on(0, 69, 100);
+        host.run(4800)
+    };
+    let (unpanned_left, unpanned_right) = render(0f32);
+    for pan_law in 1..4 {
+        let (left, right) = render(pan_law as f32);
+        let pairs = left.iter().zip(&unpanned_left).chain(right.iter().zip(&unpanned_right));
+        for (sample, unpanned) in pairs {
+            assert!((sample - unpanned).abs() < 1e-5, "pan law {}", pan_law);
+        }
+    }
+}
+
+#[test]
+fn a_held_note_keeps_sounding_below_the_threshold() {
+    let mut host = Host::new();
+    host.set("attack", 0f32).set("decay", 40f32).set("end_threshold", -20f32);
+    // sustaining at silence and just under the threshold, neither note ends while held
+    host.set("sustain", 0f32).note_on(0, 69, 100);
+    host.set("sustain", 10f32).note_on(0, 72, 100);
+    host.render(SAMPLERATE as usize, 512);
+    assert_eq!(host.voices(), 2);
+    host.note_off(0, 69).note_off(0, 72);
+    host.render(SAMPLERATE as usize / 10, 512);
+    assert_eq!(host.voices(), 0);
+}
+
+// the envelope level of a released note every 16 frames until its voice ends
+fn release_levels(release_curve: f32, end_threshold: f32) -> Vec<f32> {
+    let mut host = Host::new();
+    let note = wmidi::Note::try_from(69).unwrap();
+    host.set("attack", 0f32).set("sustain", 127f32).set("release", 90f32);
+    host.set("release_curve", release_curve).set("end_threshold", end_threshold);
+    host.note_on(0, 69, 100).run(64);
+    host.note_off(0, 69);
+    let mut levels = Vec::new();
+    loop {
+        host.run(16);
+        match host.synth().voice_env_level(note) {
+            Some(level) => levels.push(level),
+            None => return levels,
+        }
+    }
+}
+
+#[test]
+fn the_threshold_ends_both_the_linear_and_the_exponential_release() {
+    let threshold = decibel(-40f32);
+    let linear = release_levels(0f32, -40f32);
+    let exponential = release_levels(1f32, -40f32);
+    for levels in [&linear, &exponential] {
+        let last = *levels.last().unwrap();
+        assert!(levels.iter().all(|&level| level >= threshold));
+        // the voice ends right after crossing, not at the end of the full release
+        assert!(last < threshold * 1.05 + 1e-3, "{}", last);
+    }
+    // the exponential release falls faster at first and so crosses the threshold sooner
+    assert!(exponential[linear.len() / 4] < linear[linear.len() / 4]);
+    assert!(exponential.len() < linear.len());
+    // without the threshold, the exponential release runs its full length
+    assert!(release_levels(1f32, -120f32).len() > exponential.len());
+}
+
+#[test]
+fn telemetry_reports_the_values_after_mod_wheel_and_expression() {
+    let mut host = Host::new();
+    host.set("brightness", 64f32).set("gain", -10f32).set("host_bpm", 93f32);
+    host.run(64);
+    assert!((host.get("effective_brightness") - 64f32).abs() < 1e-3);
+    assert!((host.get("effective_gain") + 10f32).abs() < 1e-3);
+    assert_eq!(host.get("bpm"), 93f32);
+    // the wheel all the way up opens the brightness up to full
+    host.cc(0, 1, 127).run(64);
+    assert!((host.get("effective_brightness") - 127f32).abs() < 1e-3);
+    host.cc(0, 1, 0).cc(0, 11, 0).run(64);
+    assert!((host.get("effective_brightness") - 64f32).abs() < 1e-3);
+    assert!((host.get("effective_gain") + 10f32 + EXPRESSION_RANGE).abs() < 1e-3);
+    // the expression is only reported, the output level stays where the gain puts it
+    let level = |expression: u8| {
+        let mut host = Host::new();
+        host.cc(0, 11, expression).note_on(0, 69, 100);
+        host.run(4800);
+        rms(&host.run(4800).0)
+    };
+    assert_eq!(level(0), level(127));
+    // the audio follows the brightness
+    let upper_share = |wheel: u8| {
+        let mut host = Host::new();
+        host.cc(0, 1, wheel).note_on(0, 69, 100);
+        host.run(4800);
+        let (left, _) = host.run(8192);
+        spectrum::energy_above(&left, SAMPLERATE, 2000f32)
+    };
+    assert!(upper_share(127) > 2f32 * upper_share(0));
+}
+
+// frequency of the newest voice of `note` after playing it
+fn played_frequency(host: &mut Host, note: u8) -> f32 {
+    host.note_on(0, note, 100);
+    host.run(64);
+    let pitch = host.synth().voice_glide(wmidi::Note::try_from(note).unwrap()).unwrap().pitch;
+    host.note_off(0, note);
+    host.run(64);
+    pitch
+}
+
+#[test]
+fn a_single_note_tuning_change_retunes_only_its_note() {
+    let mut host = Host::new();
+    let untuned = played_frequency(&mut host, 74);
+    assert!((played_frequency(&mut host, 69) - 440f32).abs() < 0.01);
+    // note 69 to semitone 81 and a half, i.e. a quarter tone above 880 Hz
+    host.midi(0, &[0xf0, 0x7f, 0x7f, 0x08, 0x02, 0x00, 0x01, 69, 81, 0x40, 0x00, 0xf7]);
+    host.run(64);
+    let expected = 880f32 * (0.5f32 / 12f32).exp2();
+    assert!((played_frequency(&mut host, 69) - expected).abs() < 0.01);
+    assert!((played_frequency(&mut host, 74) - untuned).abs() < 0.01);
+    // 7f 7f 7f leaves the note alone
+    host.midi(0, &[0xf0, 0x7f, 0x7f, 0x08, 0x02, 0x00, 0x01, 69, 0x7f, 0x7f, 0x7f, 0xf7]);
+    host.run(64);
+    assert!((played_frequency(&mut host, 69) - expected).abs() < 0.01);
+}
+
+#[test]
+fn a_bulk_tuning_dump_replaces_every_pitch() {
+    let mut host = Host::new();
+    // every note a semitone up from equal temperament
+    let mut dump = vec![0xf0, 0x7e, 0x7f, 0x08, 0x01, 0x00];
+    dump.extend_from_slice(b"semitone up     ");
+    for note in 0u8..128 {
+        dump.extend_from_slice(&[(note + 1).min(127), 0x00, 0x00]);
+    }
+    dump.extend_from_slice(&[0x00, 0xf7]);
+    host.midi(0, &dump);
+    host.run(64);
+    for note in [21u8, 60, 69, 100] {
+        let expected = 440f32 * ((note as f32 + 1f32 - 69f32) / 12f32).exp2();
+        assert!((played_frequency(&mut host, note) - expected).abs() < expected * 1e-4, "{}", note);
+    }
+}
+
+#[test]
+fn the_state_keeps_the_pitch_table() {
+    let mut host = Host::new();
+    let untuned = played_frequency(&mut host, 60);
+    // an empty state goes without a table
+    assert!(host.save_state().is_empty());
+    host.synth().set_pitch_table(Some(std::array::from_fn(|note| 100f32 + note as f32)));
+    let mut state = host.save_state();
+    let mut restored = Host::new();
+    restored.restore_state(&mut state);
+    for note in [0u8, 60, 69, 127] {
+        let expected = 100f32 + note as f32;
+        assert!((played_frequency(&mut restored, note) - expected).abs() < 0.01, "{}", note);
+    }
+    // restoring a state without a table goes back to the tuning formula
+    restored.restore_state(&mut Storage::default());
+    assert!((played_frequency(&mut restored, 60) - untuned).abs() < 0.01);
+}
+
+#[test]
+fn loading_a_tuning_in_run_does_not_allocate() {
+    let mut host = Host::new();
+    host.run(64);
+    // every sysex message is copied out of its atom, so only allocating more than for a
+    // message the synth ignores would be the tuning's own
+    let mut allocations = |message: &[u8]| {
+        host.midi(0, message).write_sequence();
+        allocations::count(|| host.run_plugin(64))
+    };
+    let change = [0x08, 0x02, 0x00, 0x01, 69, 81, 0x40, 0x00, 0xf7];
+    let ignored = allocations(&[&[0xf0, 0x7d, 0x7f][..], &change].concat());
+    let tuning = allocations(&[&[0xf0, 0x7f, 0x7f][..], &change].concat());
+    assert_eq!(tuning, ignored);
+    assert!((played_frequency(&mut host, 69) - 880f32 * (0.5f32 / 12f32).exp2()).abs() < 0.01);
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+// error in dB against the signal of the raw output of 48 voices of the base note struck a
+// little apart, measured against a reference that sums the same voice contributions sorted
+// and compensated in f64; the raw output sums the voices in the same precision as the mix
+fn stacked_voices_rounding_noise() -> f64 {
+    let mut host = Host::new();
+    host.set("raw_osc", 1f32).set("brightness", 100f32).set("stack_same_note", 1f32);
+    let onsets = (0..48).map(|index| index * 61).collect::<Vec<u32>>();
+    for onset in &onsets {
+        host.note_on(*onset, 69, 100);
+    }
+    let output = host.run(4800).0;
+    let increment = newest(&mut host).phase_increment;
+    let synth = host.synth();
+    let (w, variant) = (synth.slewed_brightness, VariantFade::new(DsfVariant::OneSided));
+    let mut phases = vec![0f32; onsets.len()];
+    let (mut error, mut signal) = (0f64, 0f64);
+    for (frame, sample) in output.iter().enumerate() {
+        let mut contributions = onsets
+            .iter()
+            .zip(phases.iter_mut())
+            .filter(|(onset, _)| **onset as usize <= frame)
+            .map(|(_, phase)| {
+                let value = shepard_tone(w, *phase, synth.base_note, 69, variant);
+                *phase = (*phase + increment).rem_euclid(std::f32::consts::TAU);
+                value as f64
+            })
+            .collect::<Vec<_>>();
+        contributions.sort_by(|a, b| a.abs().total_cmp(&b.abs()));
+        let (mut sum, mut compensation) = (0f64, 0f64);
+        for contribution in contributions {
+            let corrected = contribution - compensation;
+            let next = sum + corrected;
+            compensation = (next - sum) - corrected;
+            sum = next;
+        }
+        error += (*sample as f64 - sum).powi(2);
+        signal += sum * sum;
+    }
+    // floored, so that an exact match reads as a very low level instead of minus infinity
+    10f64 * (error / signal).max(1e-30).log10()
+}
+
+#[test]
+fn the_mix_keeps_rounding_noise_against_a_precise_sum_low() {
+    let noise = stacked_voices_rounding_noise();
+    // in f64 only the final rounding to f32 is left, near -150 dB; summing in f32 piles up
+    // about 20 dB more
+    if cfg!(feature = "f64-mix") {
+        assert!(noise < -145f64, "{} dB", noise);
+    } else {
+        assert!(noise < -120f64 && noise > -145f64, "{} dB", noise);
+    }
+}
+
+#[test]
+fn voice_env_level_follows_the_envelope_and_ends_with_the_voice() {
+    let mut host = Host::new();
+    let note = wmidi::Note::try_from(69).unwrap();
+    assert_eq!(host.synth().voice_env_level(note), None);
+    host.set("attack", 40f32).set("decay", 56f32).set("sustain", 64f32);
+    host.note_on(0, 69, 100);
+    // through the attack, the decay and into the sustain
+    for frames in [64usize, 256, 1024, 4800] {
+        host.run(frames);
+        let synth = host.synth();
+        let time = synth.current_frame as f32 / synth.samplerate;
+        let expected = ads(&synth.adsr, time);
+        let level = synth.voice_env_level(note).unwrap();
+        assert!((level - expected).abs() < 1e-4, "{} against {}", level, expected);
+    }
+    host.note_off(0, 69);
+    host.render(SAMPLERATE as usize * 2, 512);
+    assert_eq!(host.synth().voice_env_level(note), None);
+}
+
+#[test]
+fn voice_glide_reports_a_glide_in_progress() {
+    let mut host = Host::new();
+    let (from, to) = (wmidi::Note::try_from(69).unwrap(), wmidi::Note::try_from(74).unwrap());
+    host.set("portamento_mode", 1f32);
+    host.note_on(0, 69, 100);
+    host.run(64);
+    let source = host.synth().voice_glide(from).unwrap();
+    assert!(!source.gliding);
+    host.note_on(0, 74, 100);
+    // partway through the glide of about 0.135 s
+    host.run(SAMPLERATE as usize / 20);
+    let glide = host.synth().voice_glide(to).unwrap();
+    let target = source.pitch * (5f32 / 12f32).exp2();
+    assert!(glide.gliding);
+    assert!(glide.pitch > source.pitch * 1.01 && glide.pitch < target * 0.99, "{:?}", glide);
+    host.render(SAMPLERATE as usize / 4, 512);
+    let glide = host.synth().voice_glide(to).unwrap();
+    assert!(!glide.gliding && (glide.pitch - target).abs() < 0.01);
+}
+
+#[cfg(feature = "debug-automation")]
+#[test]
+fn automation_changes_records_each_change_with_its_block() {
+    let mut host = Host::new();
+    host.run(64);
+    host.run(64);
+    host.set("gain", -12f32).run(64);
+    host.run(64);
+    host.set("gain", -6f32).set("pan", 0.5).run(64);
+    let changes = host
+        .synth()
+        .automation_changes()
+        .filter(|change| change.block > 0)
+        .map(|change| (change.block, change.port, change.value))
+        .collect::<Vec<_>>();
+    assert_eq!(changes, [(2, "gain", -12f32), (4, "gain", -6f32), (4, "pan", 0.5)]);
+    // the first block records every port as it found it
+    assert!(host.synth().automation_changes().any(|change| change.block == 0));
+}
+
+#[test]
+fn fold_notes_plays_a_note_far_below_the_range_an_octave_multiple_up() {
+    let mut host = Host::new();
+    host.set("min_note", 48f32).set("max_note", 72f32).set("fold_notes", 1f32);
+    host.note_on(0, 20, 100);
+    host.run(64);
+    let tone = newest(&mut host);
+    assert!((48..=72).contains(&tone.note));
+    assert_eq!(tone.note % 12, 20 % 12);
+    let folded = wmidi::Note::try_from(tone.note).unwrap();
+    assert_eq!(tone.target_phase_increment, host.synth().midi_note_to_phase_increment(folded));
+    // without folding the note is dropped
+    let mut host = Host::new();
+    host.set("min_note", 48f32).set("max_note", 72f32).note_on(0, 20, 100);
+    host.run(64);
+    assert_eq!(host.voices(), 0);
+}
+
+#[test]
+fn trimming_the_right_channel_by_6_db_halves_it() {
+    let mut host = Host::new();
+    host.set("right_trim", -6f32).note_on(0, 69, 100);
+    // past the smoothing of the trim
+    host.run(4800);
+    let (left, right) = host.run(4800);
+    let ratio = peak(&right) / peak(&left);
+    assert!((ratio - decibel(-6f32)).abs() < 1e-3, "{}", ratio);
+    assert!((ratio - 0.5).abs() < 0.01);
+}
+
+// phases two notes start at, played 1000 frames apart
+fn starting_phases(reset_phase_on_trigger: f32) -> (f32, f32) {
+    let mut host = Host::new();
+    host.set("reset_phase_on_trigger", reset_phase_on_trigger);
+    // a note-on on the last frame of a block is handled after everything before it rendered
+    host.note_on(1000, 69, 100);
+    host.run(1000);
+    let first = newest(&mut host).phase;
+    host.note_on(1000, 74, 100);
+    host.run(1000);
+    (first, newest(&mut host).phase)
+}
+
+#[test]
+fn reset_phase_on_trigger_starts_notes_at_0_or_at_the_master_phase() {
+    assert_eq!(starting_phases(1f32), (0f32, 0f32));
+    let (first, second) = starting_phases(0f32);
+    let increment = std::f32::consts::TAU * MASTER_PHASE_PITCH / SAMPLERATE;
+    let expected = |frames: f32| (increment * frames).rem_euclid(std::f32::consts::TAU);
+    assert!((first - expected(1000f32)).abs() < 1e-3, "{}", first);
+    assert!((second - expected(2000f32)).abs() < 1e-3, "{}", second);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn offline_quality_raises_the_oversampling_while_bouncing() {
+    let mut host = Host::new();
+    host.note_on(0, 69, 100).run(64);
+    assert_eq!(host.synth().oversampling, 1);
+    host.set("offline_quality", 1f32).run(64);
+    assert_eq!(host.synth().oversampling, 1);
+    // the host renders faster than real time
+    host.set("free_wheeling", 1f32).run(64);
+    assert_eq!(host.synth().oversampling, OFFLINE_OVERSAMPLING);
+    host.set("free_wheeling", 0f32).run(64);
+    assert_eq!(host.synth().oversampling, 1);
+    host.set("offline_quality", 2f32).run(64);
+    assert_eq!(host.synth().oversampling, OFFLINE_OVERSAMPLING);
+    // the held note carries on through the switches
+    assert_eq!(host.voices(), 1);
+}
+
+#[test]
+fn each_pan_law_attenuates_the_centre_against_the_sides_as_documented() {
+    for (pan_law, attenuation) in [
+        (PanLaw::ZeroDb, 0f32),
+        (PanLaw::Minus3Db, -3f32),
+        (PanLaw::Minus4_5Db, -4.5),
+        (PanLaw::Minus6Db, -6f32),
+    ] {
+        let (centre, _) = pan_gains(0f32, pan_law);
+        let (side, _) = pan_gains(-1f32, pan_law);
+        let measured = 20f32 * (centre / side).log10();
+        assert!((measured - attenuation).abs() < 0.05, "{:?}: {} dB", pan_law, measured);
+        // the output keeps the centre at unity, so the sides come up by as much
+        let (side, _) = unity_pan_gains(-1f32, pan_law);
+        assert!((20f32 * side.log10() + attenuation).abs() < 0.05, "{:?}", pan_law);
+    }
+}
+
+// peak of the left output of a note, with the given amplitude keytracking
+fn keytracked_peak(note: u8, amp_keytrack: f32) -> f32 {
+    let mut host = Host::new();
+    host.set("amp_keytrack", amp_keytrack).note_on(0, note, 100);
+    peak(&host.run(4800).0)
+}
+
+#[test]
+fn amp_keytrack_raises_high_notes_and_lowers_low_ones() {
+    // 6 dB per octave, two octaves either side of the centre
+    let high = keytracked_peak(84, 6f32) / keytracked_peak(84, 0f32);
+    let low = keytracked_peak(36, 6f32) / keytracked_peak(36, 0f32);
+    assert!((high - decibel(12f32)).abs() < 1e-3, "{}", high);
+    assert!((low - decibel(-12f32)).abs() < 1e-3, "{}", low);
+    assert!(keytracked_peak(84, 6f32) > keytracked_peak(36, 6f32));
+}
+
+// plays detached notes and reports whether each one after the first glides
+fn detached_glides(portamento_mode: f32) -> Vec<bool> {
+    let mut host = Host::new();
+    host.set("portamento_mode", portamento_mode);
+    let mut glides = vec![];
+    for (index, note) in [69u8, 74, 71, 76].into_iter().enumerate() {
+        host.note_on(0, note, 100);
+        host.run(64);
+        if index > 0 {
+            glides.push(newest(&mut host).glide_frames > 0);
+        }
+        host.note_off(0, note);
+        // until the voice has finished
+        host.render(SAMPLERATE as usize, 512);
+    }
+    glides
+}
+
+#[test]
+fn free_portamento_glides_between_detached_notes() {
+    assert_eq!(detached_glides(2f32), [true, true, true]);
+    // fingered portamento only glides between overlapping notes
+    assert_eq!(detached_glides(1f32), [false, false, false]);
+}
+
+// attack and release scales of a chord's voices
+fn envelope_scales(humanize: f32) -> Vec<(f32, f32)> {
+    let mut host = Host::new();
+    host.set("humanize", humanize);
+    for note in [60u8, 64, 67, 71] {
+        host.note_on(0, note, 100);
+    }
+    host.run(64);
+    host.synth().active_tones.values().map(|tone| (tone.attack_scale, tone.release_scale)).collect()
+}
+
+#[test]
+fn humanize_varies_the_envelope_timing_of_each_voice() {
+    assert!(envelope_scales(0f32).iter().all(|&scales| scales == (1f32, 1f32)));
+    let scales = envelope_scales(20f32);
+    for (index, &(attack, release)) in scales.iter().enumerate() {
+        assert!((0.8..=1.2).contains(&attack) && (0.8..=1.2).contains(&release));
+        assert!(scales[index + 1..].iter().all(|&(other, _)| other != attack));
+    }
+}
+
+#[test]
+fn a_note_released_during_the_attack_only_falls_from_its_level() {
+    let mut host = Host::new();
+    let note = wmidi::Note::try_from(69).unwrap();
+    host.set("attack", 80f32).set("release", 64f32);
+    host.run(64);
+    assert!((host.synth().adsr.attack - 1f32).abs() < 1e-3);
+    host.note_on(0, 69, 100);
+    // 5 ms into the attack
+    host.note_off(240, 69).run(240);
+    let at_release = host.synth().voice_env_level(note).unwrap();
+    assert!((at_release - 0.005).abs() < 1e-4, "{}", at_release);
+    let mut previous = at_release;
+    while let Some(level) = host.synth().voice_env_level(note) {
+        assert!(level <= previous, "{} after {}", level, previous);
+        previous = level;
+        host.run(16);
+    }
+}
+
+// a second of a held note with the given stereo detune in cents
+fn stereo_detuned(stereo_detune: f32) -> (Vec<f32>, Vec<f32>) {
+    let mut host = Host::new();
+    host.set("stereo_detune", stereo_detune).note_on(0, 69, 100);
+    host.render(SAMPLERATE as usize, 512)
+}
+
+#[test]
+fn stereo_detune_decorrelates_the_channels_and_keeps_the_mono_level() {
+    let (left, right) = stereo_detuned(0f32);
+    assert_eq!(left, right);
+    let original = rms(&left);
+    // over the first tenth of a second, before the slowest beating has come round
+    let window = SAMPLERATE as usize / 10;
+    let mut previous = correlation(&left[..window], &right[..window]);
+    for stereo_detune in [2f32, 10f32, 50f32] {
+        let (left, right) = stereo_detuned(stereo_detune);
+        let correlation = correlation(&left[..window], &right[..window]);
+        assert!(correlation < previous, "{} cents: {}", stereo_detune, correlation);
+        previous = correlation;
+        // the left channel is the original voice, and the mono sum keeps about its level
+        let mono = left.iter().zip(&right).map(|(l, r)| (l + r) / 2f32).collect::<Vec<_>>();
+        let level = 20f32 * (rms(&mono) / original).log10();
+        assert!(level > -4f32 && level < 0.5, "{} cents: {} dB", stereo_detune, level);
+    }
+}
+
+// a second of the summation formula with partials spaced `spacing` Hz apart from `carrier`
+fn summation(variant: DsfVariant, w: f32, carrier: f32, spacing: f32) -> Vec<f32> {
+    (0..SAMPLERATE as usize)
+        .map(|index| {
+            let time = index as f32 / SAMPLERATE;
+            let u = (std::f32::consts::TAU * carrier * time).rem_euclid(std::f32::consts::TAU);
+            let v = (std::f32::consts::TAU * spacing * time).rem_euclid(std::f32::consts::TAU);
+            dsf(variant, w, u, v)
+        })
+        .collect()
+}
+
+#[test]
+fn the_two_sided_variant_adds_the_partials_below_the_carrier() {
+    // the default variant is the plain one-sided sum
+    for (u, v) in [(0.3f32, 0.1f32), (2f32, 1f32), (5f32, 6f32)] {
+        assert_eq!(dsf(DsfVariant::OneSided, 0.5, u, v), dsf_inf(0.5, u, v));
+    }
+    let (carrier, spacing) = (1000f32, 200f32);
+    let one_sided = summation(DsfVariant::OneSided, 0.5, carrier, spacing);
+    let two_sided = summation(DsfVariant::TwoSided, 0.5, carrier, spacing);
+    let below = |buf: &[f32]| {
+        1f32 - spectrum::energy_above(buf, SAMPLERATE, carrier - spacing / 2f32)
+    };
+    assert!(below(&one_sided) < 1e-3, "{}", below(&one_sided));
+    // the mirrored partials weigh w^2k like the ones above, which with w = 0.5 puts a fifth of
+    // the energy below the carrier
+    assert!((below(&two_sided) - 0.2).abs() < 0.02, "{}", below(&two_sided));
+    assert_eq!(spectrum::dominant_frequency(&one_sided, SAMPLERATE).round(), carrier);
+    assert_eq!(spectrum::dominant_frequency(&two_sided, SAMPLERATE).round(), carrier);
+}
+
+// cutoff scale of the filter of a note, and whether the voice's coefficients are those of
+// the scaled cutoff
+fn keytracked_filter(note: u8, filter_keytrack: f32) -> (f32, bool) {
+    let mut host = Host::new();
+    host.set("filter_cutoff", 500f32).set("filter_keytrack", filter_keytrack);
+    host.note_on(0, note, 100).run(64);
+    let tone = newest(&mut host);
+    let synth = host.synth();
+    let expected = svf_coefficients(
+        500f32 * tone.cutoff_scale,
+        synth.filter_resonance,
+        synth.self_osc,
+        synth.samplerate,
+    );
+    (tone.cutoff_scale, format!("{:?}", tone.filter) == format!("{:?}", expected))
+}
+
+#[test]
+fn full_filter_keytrack_scales_the_cutoff_with_the_pitch() {
+    let (high, high_applied) = keytracked_filter(84, 1f32);
+    let (low, low_applied) = keytracked_filter(36, 1f32);
+    assert!(high_applied && low_applied);
+    // two octaves either side of the centre
+    assert!((high - 4f32).abs() < 1e-4 && (low - 0.25).abs() < 1e-4, "{} {}", high, low);
+    // without keytracking every note gets the same cutoff
+    assert_eq!(keytracked_filter(84, 0f32).0, 1f32);
+    assert_eq!(keytracked_filter(36, 0f32).0, 1f32);
+}
+
+// left and right output of a note with the given polarity toggles
+fn polarity(invert_left: f32, invert_right: f32) -> (Vec<f32>, Vec<f32>) {
+    let mut host = Host::new();
+    host.set("invert_left", invert_left).set("invert_right", invert_right);
+    host.note_on(0, 69, 100);
+    host.run(4800)
+}
+
+#[test]
+fn inverting_a_channel_negates_it_exactly() {
+    let (left, right) = polarity(0f32, 0f32);
+    let (inverted_left, same_right) = polarity(1f32, 0f32);
+    let (same_left, inverted_right) = polarity(0f32, 1f32);
+    assert_eq!(inverted_left, left.iter().map(|sample| -sample).collect::<Vec<_>>());
+    assert_eq!(inverted_right, right.iter().map(|sample| -sample).collect::<Vec<_>>());
+    assert_eq!((same_left, same_right), (left, right));
+}
+
+#[test]
+fn a_trigger_envelope_ignores_an_early_note_off() {
+    let mut host = Host::new();
+    let note = wmidi::Note::try_from(69).unwrap();
+    host.set("env_mode", 1f32).set("attack", 40f32).set("decay", 72f32);
+    host.note_on(0, 69, 100);
+    // released 10 ms in, during the decay
+    host.note_off(480, 69).run(480);
+    for _ in 0..20 {
+        host.run(1200);
+        let synth = host.synth();
+        let time = synth.current_frame as f32 / synth.samplerate;
+        let expected = ad(&synth.adsr, time);
+        assert_eq!(synth.voice_env_level(note), expected, "at {} s", time);
+    }
+    // the decay of about 0.37 s has run its course
+    assert_eq!(host.voices(), 0);
+}
+
+#[test]
+fn the_tuner_tone_is_a_pure_sine_at_the_reference() {
+    let mut host = Host::new();
+    host.set("tuner_tone", 1f32).set("tuning_hz", 432f32);
+    host.note_on(0, 60, 100).note_on(0, 67, 100);
+    host.run(4800);
+    let (left, right) = host.run(8192);
+    assert_eq!(left, right);
+    assert!((spectrum::dominant_frequency(&left, SAMPLERATE) - 432f32).abs() < 0.5);
+    assert!(spectrum::energy_above(&left, SAMPLERATE, 500f32) < 1e-3);
+    assert!(spectrum::energy_above(&left, SAMPLERATE, 380f32) > 0.999);
+    // switched off, the held notes play again and end with their note-offs
+    host.set("tuner_tone", 0f32).run(4800);
+    host.note_off(0, 60).note_off(0, 67);
+    host.render(SAMPLERATE as usize * 2, 512);
+    assert_eq!(host.voices(), 0);
+    assert!(peak(&host.run(512).0) < 1e-6);
+}
+
+#[test]
+fn stacked_restrikes_sound_together_and_release_oldest_first() {
+    let mut host = Host::new();
+    host.set("stack_same_note", 1f32).set("release", 100f32);
+    host.note_on(0, 60, 100).note_on(100, 60, 100).run(480);
+    assert_eq!(host.voices(), 2);
+    let mut voices = host.synth().active_tones.keys().copied().collect::<Vec<_>>();
+    voices.sort();
+    let released = |host: &mut Host, voice: u64| host.synth().active_tones[&voice].time_released;
+    host.note_off(0, 60).run(480);
+    assert!(released(&mut host, voices[0]).is_some());
+    assert!(released(&mut host, voices[1]).is_none());
+    host.note_off(0, 60).run(480);
+    assert!(released(&mut host, voices[1]).is_some());
+    // without stacking the restrike replaces the voice
+    let mut host = Host::new();
+    host.note_on(0, 60, 100).note_on(100, 60, 100).run(480);
+    assert_eq!(host.voices(), 1);
+}
+
+#[test]
+fn coarse_bends_glide_smoothly() {
+    let mut host = Host::new();
+    host.note_on(0, 69, 100).run(480);
+    let mut previous = 0f32;
+    // half way up and then all the way up the default range of two semitones
+    for (lsb, msb, target) in [(0u8, 0x60u8, 1f32), (0x7f, 0x7f, 2f32 * 8191f32 / 8192f32)] {
+        host.midi(0, &[0xe0, lsb, msb]);
+        for _ in 0..4800 {
+            host.run(1);
+            let bend = host.synth().pitch_bend_smoother.value;
+            assert!(bend >= previous && bend - previous < 0.01, "{} after {}", bend, previous);
+            previous = bend;
+        }
+        assert!((previous - target).abs() < 1e-3, "{} for {}", previous, target);
+    }
+}
+
+// keys left sounding after a fourth note steals from three still in their attack, so that
+// the oldest voice is also the loudest and the newest the quietest
+fn keys_after_stealing(steal_priority: f32) -> Vec<u8> {
+    let mut host = Host::new();
+    host.set("max_voices", 3f32).set("steal_priority", steal_priority).set("attack", 100f32);
+    for note in [60, 72, 64] {
+        host.note_on(0, note, 100).run(4800);
+    }
+    host.note_on(0, 71, 100).run(480);
+    let mut keys = host.synth().active_tones.values().map(|tone| tone.key).collect::<Vec<_>>();
+    keys.sort();
+    keys
+}
+
+#[test]
+fn each_steal_priority_picks_its_victim() {
+    assert_eq!(keys_after_stealing(0f32), vec![64, 71, 72]);
+    assert_eq!(keys_after_stealing(1f32), vec![60, 71, 72]);
+    assert_eq!(keys_after_stealing(2f32), vec![60, 64, 71]);
+}
+
+#[test]
+fn a_curved_decay_still_lands_on_the_sustain() {
+    let adsr = |decay_curve| Adsr {
+        attack: 0.1,
+        decay: 1f32,
+        sustain: 0.5,
+        release: 1f32,
+        decay_curve,
+        release_curve: 0f32,
+        end_level: 0f32,
+    };
+    let (linear, curved) = (adsr(0f32), adsr(1f32));
+    // the decay from 1 to 0.5 takes 0.5 s at the slope of 1 per decay time
+    assert!((ads(&linear, 0.35) - 0.75).abs() < 1e-6);
+    assert!(ads(&curved, 0.35) < 0.7);
+    for adsr in [linear, curved] {
+        assert!((ads(&adsr, 0.6 - 1e-4) - 0.5).abs() < 1e-3);
+        assert_eq!(ads(&adsr, 0.6), 0.5);
+        assert_eq!(ads(&adsr, 2f32), 0.5);
+    }
+}
+
+// the echoes of a mono impulse through a delay of 100 frames, as frame and level per channel
+fn echoes(ping_pong: bool) -> Vec<(usize, f32, f32)> {
+    let mut delay = Delay::new(SAMPLERATE);
+    (0..450)
+        .map(|frame| {
+            let input = if frame == 0 { 1f32 } else { 0f32 };
+            let (left, right) = delay.process(input, input, 100, 0.5, ping_pong);
+            (frame, left, right)
+        })
+        .filter(|&(_, left, right)| left != 0f32 || right != 0f32)
+        .collect()
+}
+
+#[test]
+fn ping_pong_echoes_alternate_between_the_channels() {
+    assert_eq!(
+        echoes(true),
+        vec![(100, 1f32, 0f32), (200, 0f32, 0.5), (300, 0.25, 0f32), (400, 0f32, 0.125)]
+    );
+    assert_eq!(
+        echoes(false),
+        vec![(100, 1f32, 1f32), (200, 0.5, 0.5), (300, 0.25, 0.25), (400, 0.125, 0.125)]
+    );
+}
+
+// peak to peak pitch swing in semitones of a sine voice over a second, from the periods
+// between its rising zero crossings
+fn vibrato_swing(lfo_depth: f32, aftertouch: u8) -> f32 {
+    let mut host = Host::new();
+    host.set("brightness", 0f32).set("lfo_depth", lfo_depth);
+    host.set("aftertouch_to_vibrato", 1f32);
+    host.note_on(0, 69, 100).midi(0, &[0xd0, aftertouch]).run(4800);
+    let output = host.render(SAMPLERATE as usize, 4800).0;
+    let crossings = output
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] < 0f32 && pair[1] >= 0f32)
+        .map(|(frame, pair)| frame as f32 + pair[0] / (pair[0] - pair[1]))
+        .collect::<Vec<_>>();
+    let periods = crossings.windows(2).map(|pair| pair[1] - pair[0]).collect::<Vec<_>>();
+    let longest = periods.iter().copied().fold(0f32, f32::max);
+    let shortest = periods.iter().copied().fold(f32::MAX, f32::min);
+    12f32 * (longest / shortest).log2()
+}
+
+#[test]
+fn aftertouch_deepens_the_vibrato() {
+    assert!(vibrato_swing(0f32, 0) < 0.01);
+    // a full press adds a semitone each way
+    assert!((vibrato_swing(0f32, 127) - 2f32).abs() < 0.1);
+    assert!((vibrato_swing(0.5, 0) - 1f32).abs() < 0.1);
+    assert!((vibrato_swing(0.5, 127) - 3f32).abs() < 0.1);
+}
+
+#[test]
+fn a_note_starts_at_its_own_frame_within_the_block() {
+    let mut host = Host::new();
+    host.note_on(100, 69, 100);
+    let (left, right) = host.run(256);
+    assert_eq!(peak(&left[..101]), 0f32);
+    assert_eq!(peak(&right[..101]), 0f32);
+    assert!(peak(&left[101..]) > 0f32);
+}
+
+// the output a quarter second after a held note drops from full gain to -60 dB
+fn gated_tail(gate_threshold: f32) -> Vec<f32> {
+    let mut host = Host::new();
+    host.set("gate_threshold", gate_threshold).set("gate_release", 50f32);
+    host.note_on(0, 69, 100).run(4800);
+    host.set("gain", -60f32).render(12000, 512);
+    host.run(4800).0
+}
+
+#[test]
+fn the_gate_silences_a_signal_below_the_threshold() {
+    // the stages after the gate leave at most a vanishing tail
+    assert!(peak(&gated_tail(-30f32)) < 1e-9);
+    // at the lowest threshold the gate is off and the quiet note carries on
+    assert!(peak(&gated_tail(-96f32)) > 1e-4);
+}
+
+// energy of the sample to sample differences over the start of a soft sine note, which the
+// note itself barely contributes to
+fn onset_roughness(click: f32) -> f32 {
+    let mut host = Host::new();
+    host.set("brightness", 0f32).set("click", click);
+    let output = host.note_on(0, 57, 100).run(256).0;
+    output.windows(2).map(|pair| (pair[1] - pair[0]).powi(2)).sum()
+}
+
+#[test]
+fn a_click_adds_high_frequencies_to_the_onset() {
+    let (plain, half, full) = (onset_roughness(0f32), onset_roughness(0.5), onset_roughness(1f32));
+    assert!(half > 10f32 * plain, "{} against {}", half, plain);
+    assert!(full > 2f32 * half, "{} against {}", full, half);
+}
+
+#[test]
+fn rpn_fine_and_coarse_tuning_retune_the_notes() {
+    let mut host = Host::new();
+    // data entry without a selected parameter changes nothing
+    host.cc(0, 6, 0x50).cc(0, 38, 0);
+    assert!((played_frequency(&mut host, 69) - 440f32).abs() < 0.01);
+    // fine tuning 0x3000 above the centre is half of the range of a semitone up
+    host.cc(0, 101, 0).cc(0, 100, 1).cc(0, 6, 0x60).cc(0, 38, 0);
+    let fine = 440f32 * (0.5f32 / 12f32).exp2();
+    assert!((played_frequency(&mut host, 69) - fine).abs() < 0.01);
+    // coarse tuning two semitones down on top of it
+    host.cc(0, 101, 0).cc(0, 100, 2).cc(0, 6, 62).cc(0, 38, 0);
+    let coarse = 440f32 * (-1.5f32 / 12f32).exp2();
+    assert!((played_frequency(&mut host, 69) - coarse).abs() < 0.01);
+    // after the null parameter further data entry is ignored
+    host.cc(0, 101, 127).cc(0, 100, 127).cc(0, 6, 64);
+    assert!((played_frequency(&mut host, 69) - coarse).abs() < 0.01);
+}
+
+// pitch a new voice of `note` starts from in cascade mode, from a note-on at the last frame
+// of a block
+fn cascade_start(host: &mut Host, note: u8) -> f32 {
+    host.note_on(63, note, 100).run(64);
+    let synth = host.synth();
+    let glide = synth.voice_glide(wmidi::Note::try_from(note).unwrap()).unwrap();
+    assert!(glide.gliding);
+    glide.pitch
+}
+
+#[test]
+fn cascade_portamento_glides_each_voice_from_the_previous_one() {
+    let mut host = Host::new();
+    host.set("portamento_mode", 3f32).set("portamento_time", 64f32);
+    let frequency = |note: u8| 440f32 * ((note as f32 - 69f32) / 12f32).exp2();
+    host.note_on(0, 69, 100).run(64);
+    // every voice of the chord glides from the one struck before it, which is still held
+    assert!((cascade_start(&mut host, 74) / frequency(69) - 1f32).abs() < 1e-3);
+    assert!((cascade_start(&mut host, 71) / frequency(74) - 1f32).abs() < 1e-3);
+    assert_eq!(host.voices(), 3);
+    // a release counts as well, from the pitch the released voice had reached
+    host.render(SAMPLERATE as usize, 512);
+    host.note_off(0, 69).run(64);
+    assert!((cascade_start(&mut host, 72) / frequency(69) - 1f32).abs() < 1e-3);
+}
+
+// a note with delay, reverb, gate and tilt, then eight seconds of idling; an input mix with
+// a silent input keeps the full path running throughout
+fn idle(full_path: bool) -> (Host, Vec<f32>) {
+    let mut host = Host::new();
+    host.set("delay_mix", 0.5).set("reverb_mix", 0.5);
+    host.set("gate_threshold", -60f32).set("tilt", 0.5);
+    if full_path {
+        host.set("input_mix", 1f32);
+    }
+    host.note_on(0, 69, 100).run(4800);
+    host.note_off(0, 69);
+    let output = host.render(SAMPLERATE as usize * 8, 512).0;
+    (host, output)
+}
+
+#[test]
+fn idling_skips_the_work_without_changing_the_output() {
+    let ((mut fast, fast_output), (mut full, full_output)) = (idle(false), idle(true));
+    let second = SAMPLERATE as usize;
+    // the effect tails ring out after the voice has ended before the fast path takes over
+    assert!(peak(&fast_output[second..second * 2]) > 1e-3);
+    assert_eq!(peak(&fast_output[second * 7..]), 0f32);
+    let difference = fast_output.iter().zip(full_output.iter()).map(|(a, b)| (a - b).abs());
+    assert!(difference.fold(0f32, f32::max) < 1e-6);
+    // the phases move on in one step per block instead of sample by sample, which only
+    // differs in rounding
+    let wrapped = |a: f32, b: f32| {
+        let difference = (a - b).rem_euclid(std::f32::consts::TAU);
+        difference.min(std::f32::consts::TAU - difference)
+    };
+    {
+        let (fast, full) = (fast.synth(), full.synth());
+        assert!(fast.active_tones.is_empty() && fast.quiet_frames > second * 2);
+        assert!(wrapped(fast.master_phase, full.master_phase) < 0.05);
+        assert!(wrapped(fast.lfo_phase, full.lfo_phase) < 0.05);
+        // where the full path only gets ever closer to silence, the idle one lands on it
+        assert_eq!((fast.gate.gain, fast.gate.detector), (0f32, 0f32));
+        assert_eq!((fast.tilt_filter.left, fast.highpass.left_output), (0f32, 0f32));
+    }
+    // the gate and the filters come out of the idle stretch as the full path leaves them,
+    // so the next note starts the same
+    let fast_onset = fast.note_on(0, 72, 100).render(SAMPLERATE as usize / 4, 512).0;
+    let full_onset = full.note_on(0, 72, 100).render(SAMPLERATE as usize / 4, 512).0;
+    assert!(peak(&full_onset) > 0.01);
+    let difference = fast_onset.iter().zip(full_onset.iter()).map(|(a, b)| (a - b).abs());
+    assert!(difference.fold(0f32, f32::max) < 1e-6);
+}
+
+#[test]
+fn the_reverb_turns_an_impulse_into_a_decaying_diffuse_tail() {
+    let mut reverb = Reverb::new(SAMPLERATE);
+    let (left, right): (Vec<f32>, Vec<f32>) = (0..SAMPLERATE as usize * 2)
+        .map(|frame| {
+            let input = if frame == 0 { 1f32 } else { 0f32 };
+            reverb.process(input, input, 0.5, 0.5)
+        })
+        .unzip();
+    let quarter = SAMPLERATE as usize / 4;
+    let energies = left
+        .chunks(quarter)
+        .map(|chunk| chunk.iter().map(|sample| sample * sample).sum::<f32>())
+        .collect::<Vec<_>>();
+    assert!(energies[0] > 0f32);
+    assert!(energies.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", energies);
+    // dense rather than a few discrete echoes, and different in the two channels
+    let window = &left[quarter..quarter * 2];
+    let dense = window.iter().filter(|sample| sample.abs() > 1e-6).count();
+    assert!(dense as f32 > 0.9 * window.len() as f32);
+    assert!(correlation(window, &right[quarter..quarter * 2]).abs() < 0.5);
+}
+
+#[test]
+fn without_reverb_mix_the_output_stays_dry() {
+    let play = |size: f32, damp: f32| {
+        let mut host = Host::new();
+        host.set("reverb_size", size).set("reverb_damp", damp).set("reverb_mix", 0f32);
+        host.note_on(0, 69, 100).run(4800)
+    };
+    assert_eq!(play(0f32, 0f32), play(1f32, 1f32));
+}
+
+#[test]
+fn classic_mono_plays_the_lowest_note_with_legato_glides() {
+    let mut host = Host::new();
+    host.set("classic_mono", 1f32).set("portamento_time", 40f32);
+    host.note_on(0, 45, 100).run(4800);
+    let pressed = newest(&mut host).time_pressed;
+    // a higher note struck over the held one does not take over
+    host.note_on(0, 52, 100).run(64);
+    assert_eq!((host.voices(), newest(&mut host).key), (1, 45));
+    // releasing the low note glides up to the one still held, and a lower one takes over
+    for (note_off, note_on, key) in [(Some(45), None, 52), (None, Some(41), 41)] {
+        if let Some(note) = note_off {
+            host.note_off(0, note);
+        }
+        if let Some(note) = note_on {
+            host.note_on(0, note, 100);
+        }
+        host.run(64);
+        let tone = newest(&mut host);
+        assert_eq!((host.voices(), tone.key), (1, key));
+        assert!(tone.glide_frames > 0);
+        // the envelope carries on from the first note
+        assert_eq!((tone.time_pressed, tone.time_released), (pressed, None));
+    }
+}
+
+#[test]
+fn the_clip_port_latches_and_falls_after_the_hold() {
+    let mut host = Host::new();
+    host.set("gain", 0f32).set("brightness", 100f32);
+    for note in [60, 64, 67, 70, 72, 76] {
+        host.note_on(0, note, 127);
+    }
+    assert_eq!(host.get("clip"), 0f32);
+    assert!(peak(&host.run(4800).0) > 1f32);
+    assert_eq!(host.get("clip"), 1f32);
+    // quiet enough that nothing clips any more once the gain has ramped down
+    host.set("gain", -50f32);
+    let mut levels = vec![];
+    for _ in 0..15 {
+        assert!(peak(&host.run(4800).0) < 1f32);
+        levels.push(host.get("clip"));
+    }
+    assert!(levels[..9].iter().all(|&level| level == 1f32), "{:?}", levels);
+    assert!(levels[10] < 1f32 && levels[10] > 0f32, "{:?}", levels);
+    assert_eq!(levels[14], 0f32);
+}
+
+// share of a held note's energy above its third harmonic early on and a second later
+fn harmonics_over_time(brightness_decay: f32) -> (f32, f32) {
+    let mut host = Host::new();
+    host.set("brightness", 100f32).set("brightness_decay", brightness_decay);
+    host.note_on(0, 69, 100).run(4800);
+    let early = host.run(8192).0;
+    host.render(SAMPLERATE as usize, 4800);
+    let late = host.run(8192).0;
+    let share = |output: &[f32]| spectrum::energy_above(output, SAMPLERATE, 1500f32);
+    (share(&early), share(&late))
+}
+
+#[test]
+fn brightness_decay_dulls_a_note_as_it_rings() {
+    let (early, late) = harmonics_over_time(2f32);
+    assert!(late < 0.5 * early, "{} then {}", early, late);
+    let (early, late) = harmonics_over_time(0f32);
+    assert!((late - early).abs() < 1e-3, "{} then {}", early, late);
+}
+
+#[test]
+fn a_mapped_cc_drives_its_destination() {
+    let mut host = Host::new();
+    host.set("brightness", 64f32).set("cc_source", 20f32).set("cc_dest", 1f32);
+    host.run(64);
+    assert!((host.get("effective_brightness") - 64f32).abs() < 1e-3);
+    for (value, brightness) in [(127u8, 127f32), (0, 0f32), (32, 32f32)] {
+        host.cc(0, 20, value).run(64);
+        assert!((host.get("effective_brightness") - brightness).abs() < 1e-3);
+    }
+    // without a destination the controller changes nothing
+    let mut host = Host::new();
+    host.set("brightness", 64f32).set("cc_source", 20f32).cc(0, 20, 127).run(64);
+    assert!((host.get("effective_brightness") - 64f32).abs() < 1e-3);
+}
+
+#[test]
+fn a_mapped_cc_leaves_its_hard_wired_meaning_alone() {
+    // the mod wheel mapped to the brightness sets it without also brightening it
+    let mut host = Host::new();
+    host.set("brightness", 64f32).set("cc_source", 1f32).set("cc_dest", 1f32);
+    host.cc(0, 1, 32).run(64);
+    assert_eq!(host.synth().mod_wheel, 0f32);
+    assert!((host.get("effective_brightness") - 32f32).abs() < 1e-3);
+    // nor does the damper pedal mapped to the gain hold released notes
+    let mut host = Host::new();
+    host.set("cc_source", 64f32).set("cc_dest", 2f32).set("release", 10f32);
+    host.note_on(0, 69, 100).cc(0, 64, 127).run(480);
+    assert_eq!(host.synth().sustain_pedal, 0f32);
+    assert!((host.get("effective_gain") - 0f32).abs() < 1e-3);
+    host.note_off(0, 69).render(SAMPLERATE as usize, 512);
+    assert_eq!(host.voices(), 0);
+}
+
+// share of the energy of a bright note above the tilt pivot
+fn tilted_highs(tilt: f32) -> f32 {
+    let mut host = Host::new();
+    host.set("brightness", 100f32).set("tilt", tilt);
+    host.note_on(0, 57, 100).run(4800);
+    spectrum::energy_above(&host.run(8192).0, SAMPLERATE, TILT_PIVOT)
+}
+
+#[test]
+fn the_tilt_shifts_the_balance_between_lows_and_highs() {
+    let shares = [-1f32, -0.5, 0f32, 0.5, 1f32].map(tilted_highs);
+    assert!(shares.windows(2).all(|pair| pair[1] > pair[0] + 0.02), "{:?}", shares);
+    // a flat tilt leaves the note as it is
+    let mut host = Host::new();
+    host.set("brightness", 100f32).note_on(0, 57, 100).run(4800);
+    assert_eq!(spectrum::energy_above(&host.run(8192).0, SAMPLERATE, TILT_PIVOT), shares[2]);
+}
+
+// unbent pitch a fingered glide from 440 Hz starts at under a full bend up of two
+// semitones, relative to 440 Hz: 1 to start from the bent pitch once the bend is applied
+// on top, and 2^(-1/6) to start from the unbent one
+fn bent_glide_start(glide_bend: f32) -> f32 {
+    let mut host = Host::new();
+    host.set("portamento_mode", 1f32).set("glide_bend", glide_bend);
+    host.note_on(0, 69, 100).midi(0, &[0xe0, 0x7f, 0x7f]).run(4800);
+    host.note_on(63, 74, 100).run(64);
+    let synth = host.synth();
+    let glide = synth.voice_glide(wmidi::Note::try_from(74).unwrap()).unwrap();
+    let bend = synth.pitch_bend_smoother.value;
+    assert!(glide.gliding && (bend - 2f32).abs() < 1e-2);
+    glide.pitch / 440f32
+}
+
+#[test]
+fn a_glide_under_a_bend_starts_from_the_selected_pitch() {
+    assert!((bent_glide_start(0f32) - 1f32).abs() < 1e-3);
+    assert!((bent_glide_start(1f32) - (-2f32 / 12f32).exp2()).abs() < 1e-3);
+}
+
+// start frames of the voices of a chord sent in one block, from the lowest key up
+fn strummed_starts(strum: f32) -> Vec<(u8, u64)> {
+    let mut host = Host::new();
+    host.set("strum", strum);
+    host.note_on(0, 67, 100).note_on(0, 60, 100).note_on(0, 64, 100).run(64);
+    let synth = host.synth();
+    let mut starts =
+        synth.active_tones.values().map(|tone| (tone.key, tone.time_pressed)).collect::<Vec<_>>();
+    starts.sort();
+    starts
+}
+
+#[test]
+fn a_strum_staggers_the_chord_from_the_lowest_note() {
+    // 10 ms apart at 48 kHz
+    assert_eq!(strummed_starts(10f32), vec![(60, 0), (64, 480), (67, 960)]);
+    assert_eq!(strummed_starts(0f32), vec![(60, 0), (64, 0), (67, 0)]);
+}
+
+#[test]
+fn onset_smoothing_ramps_in_a_zero_attack_note() {
+    let play = |click_smooth: f32| {
+        let mut host = Host::new();
+        host.set("attack", 0f32).set("click_smooth", click_smooth);
+        host.note_on(0, 69, 100).run(256).0
+    };
+    let (hard, smoothed) = (play(0f32), play(0.5));
+    // 0.5 ms are 24 frames at 48 kHz, over which the level rises from 0 to full; the filters
+    // after the voices only let the ratio drift away from the ramp a little
+    let ratios = (1..24).map(|age| smoothed[age] / hard[age]).collect::<Vec<_>>();
+    assert_eq!(smoothed[0], 0f32);
+    assert!((ratios[0] - 1f32 / 24f32).abs() < 1e-4, "{:?}", ratios);
+    assert!(ratios.windows(2).all(|pair| pair[1] > pair[0]), "{:?}", ratios);
+    assert!((ratios[22] - 1f32).abs() < 0.05, "{:?}", ratios);
+}
+
+// output of a block of a 1 kHz sine on both audio inputs, without any notes
+fn through_input(input_mix: f32) -> (Vec<f32>, Vec<f32>) {
+    let mut host = Host::new();
+    let sine = (0..4800)
+        .map(|frame| 0.5 * (std::f32::consts::TAU * 1000f32 * frame as f32 / SAMPLERATE).sin())
+        .collect::<Vec<_>>();
+    host.set("input_mix", input_mix);
+    host.input("left_audio_input", &sine).input("right_audio_input", &sine);
+    host.run(4800);
+    (sine, host.run(4800).0)
+}
+
+#[test]
+fn the_audio_input_is_mixed_into_the_effects() {
+    let (sine, output) = through_input(0.5);
+    assert!(correlation(&sine, &output) > 0.999);
+    assert!((rms(&output) / rms(&sine) - 0.5).abs() < 0.01);
+    assert_eq!(peak(&through_input(0f32).1), 0f32);
+    // an impulse on the input comes back out of the delay 50 ms later
+    let mut host = Host::new();
+    let mut impulse = vec![0f32; 4800];
+    impulse[0] = 1f32;
+    host.set("input_mix", 1f32).set("delay_mix", 0.5).set("delay_time", 50f32);
+    host.input("left_audio_input", &impulse).input("right_audio_input", &impulse);
+    let output = host.run(4800).0;
+    assert!((output[2400] - 0.5).abs() < 0.01, "{}", output[2400]);
+    assert!(peak(&output[1..2400]) < 0.01);
+}
+
+// keys sounding after a third note joins two voices of a limit of two, one of them releasing
+fn keys_with_a_release(release_counts: f32) -> Vec<u8> {
+    let mut host = Host::new();
+    host.set("max_voices", 2f32).set("release_counts", release_counts).set("release", 100f32);
+    host.note_on(0, 60, 100).note_on(0, 64, 100).run(480);
+    host.note_off(0, 60).run(480);
+    host.note_on(0, 67, 100).run(480);
+    let mut keys = host.synth().active_tones.values().map(|tone| tone.key).collect::<Vec<_>>();
+    keys.sort();
+    keys
+}
+
+#[test]
+fn releasing_voices_free_their_slot_unless_they_count() {
+    // the oldest voice, which is the releasing one, makes way
+    assert_eq!(keys_with_a_release(1f32), vec![64, 67]);
+    assert_eq!(keys_with_a_release(0f32), vec![60, 64, 67]);
+}
+
+#[test]
+fn muting_fades_the_output_out_and_back_in() {
+    let (mut muted, mut reference) = (Host::new(), Host::new());
+    for host in [&mut muted, &mut reference] {
+        host.note_on(0, 69, 100).run(4800);
+    }
+    let mut gains = vec![];
+    for mute in [1f32, 0f32] {
+        muted.set("mute", mute);
+        let (output, expected) = (muted.run(4800).0, reference.run(4800).0);
+        // the gain applied, read off wherever the unmuted output is far enough from zero
+        gains.extend(output.iter().zip(expected.iter()).map(|(sample, expected)| {
+            (expected.abs() > 1e-3).then(|| sample / expected)
+        }));
+        assert_eq!(muted.voices(), 1);
+    }
+    // silent by the end of the first block and back at full gain by the end of the second
+    assert!(gains[4000..4800].iter().flatten().all(|&gain| gain == 0f32));
+    assert!(gains[8800..].iter().flatten().all(|&gain| gain == 1f32));
+    let gains = gains.into_iter().flatten().collect::<Vec<_>>();
+    assert!((gains[0] - 1f32).abs() < 0.1);
+    // without a jump in either direction
+    assert!(gains.windows(2).all(|pair| (pair[1] - pair[0]).abs() < 0.05));
+}
+
+// how far the lfo moves on over `frames`, in cycles
+fn lfo_cycles(host: &mut Host, frames: usize) -> f32 {
+    let start = host.synth().lfo_phase;
+    host.render(frames, 4800);
+    let end = host.synth().lfo_phase;
+    (end - start).rem_euclid(std::f32::consts::TAU) / std::f32::consts::TAU
+}
+
+#[test]
+fn a_synced_lfo_follows_the_host_tempo() {
+    let mut host = Host::new();
+    host.set("lfo_sync", 1f32).set("lfo_division", 2f32).set("host_bpm", 100f32);
+    host.set("lfo_rate", 7f32).note_on(0, 69, 100).run(64);
+    // a beat at 100 bpm is 0.6 s, and a quarter note lasts a cycle
+    let cycles = lfo_cycles(&mut host, 28800);
+    assert!(cycles.min(1f32 - cycles) < 1e-3, "{}", cycles);
+    assert!((lfo_cycles(&mut host, 14400) - 0.5).abs() < 1e-3);
+    // an eighth note triplet makes three cycles of the beat
+    host.set("lfo_division", 4f32);
+    assert!((lfo_cycles(&mut host, 4800) - 0.5).abs() < 1e-3);
+    // without sync the rate is in hertz
+    host.set("lfo_sync", 0f32);
+    assert!((lfo_cycles(&mut host, 4800) - 0.7).abs() < 1e-3);
+}
+
+// level of a note on a midi channel from 0, with the gain trims of the first two channels
+fn channel_level(channel: u8, channel_gains: [f32; 2]) -> f32 {
+    let mut host = Host::new();
+    host.set("omni", 1f32);
+    host.set("channel_1_gain", channel_gains[0]).set("channel_2_gain", channel_gains[1]);
+    host.midi(0, &[0x90 | channel, 69, 100]).run(4800);
+    rms(&host.run(4800).0)
+}
+
+#[test]
+fn each_channel_plays_at_its_own_gain() {
+    let trimmed = channel_level(1, [0f32, -12f32]) / channel_level(0, [0f32, -12f32]);
+    assert!((trimmed - decibel(-12f32)).abs() < 1e-3, "{}", trimmed);
+    let boosted = channel_level(0, [6f32, 0f32]) / channel_level(0, [0f32, 0f32]);
+    assert!((boosted - decibel(6f32)).abs() < 1e-3, "{}", boosted);
+    assert_eq!(channel_level(1, [0f32, 0f32]), channel_level(0, [0f32, 0f32]));
+}
+
+#[test]
+fn a_long_release_rings_out_in_full_and_then_ends() {
+    let mut host = Host::new();
+    host.set("release", 110f32);
+    host.note_on(0, 69, 100).render(SAMPLERATE as usize, 4800);
+    host.note_off(0, 69).run(1);
+    let note = wmidi::Note::try_from(69).unwrap();
+    let synth = host.synth();
+    let (release, end_level) = (synth.adsr.release, synth.adsr.end_level);
+    assert!(release > 10f32, "{}", release);
+    let val_at_release = ads(&synth.adsr, 1f32);
+    let tail = (release * (val_at_release - end_level) * SAMPLERATE) as usize;
+    host.render(tail - 65, 4800);
+    let level = host.synth().voice_env_level(note).unwrap();
+    assert!(level > 0f32 && level < 1e-3 + end_level, "{}", level);
+    let mut frames = tail - 64;
+    while host.voices() > 0 {
+        host.run(1);
+        frames += 1;
+    }
+    assert!(frames.abs_diff(tail) <= 2, "{} frames for {}", frames, tail);
+}
+
+// a note with vibrato struck `delay` frames after activation, from its onset on
+fn vibrato_from(delay: usize, lfo_retrigger: f32) -> Vec<f32> {
+    let mut host = Host::new();
+    host.set("lfo_depth", 1f32).set("lfo_retrigger", lfo_retrigger);
+    host.set("reset_phase_on_trigger", 1f32).render(delay, 4800);
+    host.note_on(0, 69, 100);
+    host.render(9600, 4800).0
+}
+
+#[test]
+fn a_retriggered_lfo_starts_each_note_from_the_same_phase() {
+    // the global lfo at 5 Hz is at different points of its cycle after 0.13 s and 0.37 s
+    let difference = |a: Vec<f32>, b: Vec<f32>| {
+        a.iter().zip(b.iter()).map(|(a, b)| (a - b).abs()).fold(0f32, f32::max)
+    };
+    assert!(difference(vibrato_from(6240, 1f32), vibrato_from(17760, 1f32)) < 1e-4);
+    assert!(difference(vibrato_from(6240, 0f32), vibrato_from(17760, 0f32)) > 0.01);
+}
+
+#[test]
+fn note_ons_beyond_the_block_limit_are_dropped() {
+    let mut host = Host::new();
+    host.set("max_noteons_per_block", 8f32);
+    for note in 40..60 {
+        host.note_on(note as u32, note, 100);
+    }
+    host.run(64);
+    assert_eq!(host.voices(), 8);
+    // the first ones of the block get through, and the limit starts over with the next block
+    let mut keys = host.synth().active_tones.values().map(|tone| tone.key).collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(keys, (40..48).collect::<Vec<_>>());
+    host.note_on(0, 70, 100).run(64);
+    assert_eq!(host.voices(), 9);
+}
+
+#[test]
+fn the_correlation_port_reports_the_mono_compatibility() {
+    let mut host = Host::new();
+    // silence sums to mono without any loss
+    host.run(64);
+    assert_eq!(host.get("correlation"), 1f32);
+    host.note_on(0, 69, 100).run(4800);
+    assert!((host.get("correlation") - 1f32).abs() < 1e-4, "{}", host.get("correlation"));
+    host.set("invert_left", 1f32).run(4800);
+    assert!((host.get("correlation") + 1f32).abs() < 1e-4, "{}", host.get("correlation"));
+}
+
+// the brightness the voices use over the 0.1 s after a step, sample by sample
+fn brightness_after_step(host: &mut Host) -> Vec<f32> {
+    (0..4800)
+        .map(|_| {
+            host.run(1);
+            host.synth().slewed_brightness
+        })
+        .collect()
+}
+
+#[test]
+fn a_brightness_step_reaches_the_voices_smoothly() {
+    let mut host = Host::new();
+    host.set("brightness", 0f32).note_on(0, 69, 100).run(4800);
+    host.set("brightness", 127f32);
+    let stepped = brightness_after_step(&mut host);
+    // by at most the default slew of 100 per second, i.e. 1/480 per sample
+    let steps = stepped.windows(2).map(|pair| pair[1] - pair[0]).collect::<Vec<_>>();
+    assert!(stepped[0] < 0.01 && (stepped[4799] - 1f32).abs() < 1e-3);
+    assert!(steps.iter().all(|&step| (0f32..=1.001 / 480f32).contains(&step)));
+    // the mod wheel opens it up through the same smoothing
+    host.set("brightness", 64f32).run(4800);
+    host.cc(0, 1, 0).run(4800);
+    host.cc(0, 1, 127);
+    let wheeled = brightness_after_step(&mut host);
+    assert!(wheeled[0] < 64f32 / 127f32 + 0.01 && (wheeled[4799] - 1f32).abs() < 1e-3);
+    assert!(wheeled.windows(2).all(|pair| (0f32..=1.001 / 480f32).contains(&(pair[1] - pair[0]))));
+}
+
+// velocity stored for a note struck with `velocity`
+fn floored_velocity(velocity_floor: f32, velocity: u8) -> f32 {
+    let mut host = Host::new();
+    host.set("velocity_floor", velocity_floor).note_on(0, 69, velocity).run(64);
+    newest(&mut host).velocity
+}
+
+#[test]
+fn the_velocity_floor_lifts_the_softest_notes() {
+    assert!(floored_velocity(0.5, 1) >= 0.5);
+    assert_eq!(floored_velocity(0.5, 127), 1f32);
+    assert!((floored_velocity(0.5, 64) - (0.5 + 0.5 * 64f32 / 127f32)).abs() < 1e-6);
+    // without a floor the velocity maps onto 0..1 as before
+    assert!((floored_velocity(0f32, 1) - 1f32 / 127f32).abs() < 1e-6);
+}
+
+// keys sounding 10 ms after 42 and 60 are joined by 46
+fn keys_after_choke(choke_1: f32) -> Vec<u8> {
+    let mut host = Host::new();
+    host.set("choke_1", choke_1).set("choke_1_low", 42f32).set("choke_1_high", 46f32);
+    host.note_on(0, 42, 100).note_on(0, 60, 100).run(480);
+    host.note_on(0, 46, 100).run(480);
+    let mut keys = host.synth().active_tones.values().map(|tone| tone.key).collect::<Vec<_>>();
+    keys.sort();
+    keys
+}
+
+#[test]
+fn a_note_chokes_the_others_of_its_group() {
+    // the open hi-hat is cut off within the 5 ms fade, the note outside the group rings on
+    assert_eq!(keys_after_choke(1f32), vec![46, 60]);
+    assert_eq!(keys_after_choke(0f32), vec![42, 46, 60]);
+}
+
+// magnitudes of the first four harmonics of a sine of `amplitude` through the saturator,
+// relative to the fundamental, with the sine on bin 64 of the transform
+fn saturated_harmonics(amplitude: f32, knee: f32, symmetry: f32) -> Vec<f32> {
+    let shaped = (0..8192)
+        .map(|frame| {
+            let sine = amplitude * (std::f32::consts::TAU * 64f32 * frame as f32 / 8192f32).sin();
+            saturate(sine, knee, symmetry)
+        })
+        .collect::<Vec<_>>();
+    let magnitudes = spectrum::magnitudes(&shaped);
+    (2..=5).map(|harmonic| magnitudes[64 * harmonic] / magnitudes[64]).collect()
+}
+
+#[test]
+fn asymmetric_saturation_adds_even_harmonics() {
+    let symmetric = saturated_harmonics(1.5, 0.5, 0f32);
+    assert!(symmetric[0] < 1e-4 && symmetric[2] < 1e-4, "{:?}", symmetric);
+    assert!(symmetric[1] > 0.01, "{:?}", symmetric);
+    let asymmetric = saturated_harmonics(1.5, 0.5, 0.3);
+    assert!(asymmetric[0] > 0.01 && asymmetric[2] > 0.001, "{:?}", asymmetric);
+    // a hard knee brings in more of the upper harmonics than a soft one
+    assert!(saturated_harmonics(1.5, 0f32, 0f32)[3] > saturated_harmonics(1.5, 1f32, 0f32)[3]);
+    // below the knee the default shape leaves the signal as it is
+    let quiet = saturated_harmonics(0.4, 0.5, 0f32);
+    assert!(quiet.iter().all(|&harmonic| harmonic < 1e-4), "{:?}", quiet);
+}
+
+#[test]
+fn the_metronome_clicks_on_each_beat_of_the_transport() {
+    let mut host = Host::new();
+    host.set("metronome", 1f32).set("host_bpm", 120f32).set("host_speed", 1f32);
+    // blocks of 0.2 beats at 120 bpm, the transport starting at beat 2.6 of a bar of four
+    let mut output = vec![];
+    for block in 0..15 {
+        let position = (2.6 + 0.2 * block as f64).rem_euclid(4f64);
+        host.set("host_bar_beat", position as f32);
+        output.extend(host.run(4800).0);
+    }
+    // the first frames clearly above the tail of the previous click
+    let onsets = (480..output.len())
+        .filter(|&frame| output[frame].abs() > 1e-3 && peak(&output[frame - 480..frame]) < 1e-4)
+        .collect::<Vec<_>>();
+    // beat 3 after 0.4 beats, the downbeat of the next bar after 1.4 and its beat 1 after 2.4,
+    // a frame after each as a click starts from a zero crossing
+    assert_eq!(onsets, vec![9601, 33601, 57601]);
+    let pitch =
+        |onset: usize| spectrum::dominant_frequency(&output[onset..onset + 480], SAMPLERATE);
+    assert!((pitch(9601) - 1000f32).abs() < 100f32);
+    assert!((pitch(33601) - 1500f32).abs() < 100f32);
+    assert!((pitch(57601) - 1000f32).abs() < 100f32);
+}
+
+// how much the 50 ms after a note-off with `velocity` differ from a release without the layer
+fn release_noise(release_layer: f32, velocity: u8) -> f32 {
+    let play = |release_layer: f32| {
+        let mut host = Host::new();
+        host.set("release_layer", release_layer).note_on(0, 69, 100).run(4800);
+        host.midi(0, &[0x80, 69, velocity]);
+        host.run(2400).0
+    };
+    let (layered, plain) = (play(release_layer), play(0f32));
+    rms(&layered.iter().zip(plain.iter()).map(|(a, b)| a - b).collect::<Vec<_>>())
+}
+
+#[test]
+fn a_release_layer_sounds_on_note_off() {
+    assert!(release_noise(1f32, 100) > 1e-3);
+    // scaled by the amount and the release velocity
+    assert!(release_noise(1f32, 100) > 1.5 * release_noise(0.5, 100));
+    assert!(release_noise(1f32, 100) > 1.5 * release_noise(1f32, 50));
+    assert_eq!(release_noise(1f32, 0), 0f32);
+}
+
+#[test]
+fn the_time_span_maps_the_controls_onto_min_and_max_time() {
+    // the default span is the one of the fixed mapping before it
+    for val in [0f32, 32f32, 64f32, 127f32] {
+        let span = midi_val_to_time_span(val, 0.0000454, 356f32);
+        assert!((span / midi_val_to_time(val) - 1f32).abs() < 1e-3, "at {}", val);
+    }
+    let mut host = Host::new();
+    host.set("min_time", 0.01).set("max_time", 10f32);
+    for (val, seconds) in [(0f32, 0.01), (127f32, 10f32)] {
+        host.set("attack", val).set("decay", val).set("release", val).run(64);
+        for port in ["attack_seconds", "decay_seconds", "release_seconds"] {
+            assert!((host.get(port) / seconds - 1f32).abs() < 1e-4, "{} at {}", port, val);
+        }
+    }
+}
+
+#[test]
+fn a_frozen_chord_sounds_on_through_its_note_offs() {
+    let mut host = Host::new();
+    host.set("attack", 0f32).set("decay", 70f32).set("sustain", 40f32).set("release", 40f32);
+    let chord = [60u8, 64, 67].map(|note| wmidi::Note::try_from(note).unwrap());
+    for note in chord {
+        host.note_on(0, u8::from(note), 100);
+    }
+    // frozen part way through the decay
+    host.run(4800);
+    host.set("freeze", 1f32).run(64);
+    let levels = chord.map(|note| host.synth().voice_env_level(note).unwrap());
+    assert!(levels.iter().all(|&level| level < 1f32 && level > 40f32 / 127f32));
+    for note in chord {
+        host.note_off(0, u8::from(note));
+    }
+    host.render(SAMPLERATE as usize * 2, 512);
+    assert_eq!(host.voices(), 3);
+    assert_eq!(chord.map(|note| host.synth().voice_env_level(note).unwrap()), levels);
+    // the pending releases start once unfrozen
+    host.set("freeze", 0f32).render(SAMPLERATE as usize * 2, 512);
+    assert_eq!(host.voices(), 0);
+}
+
+#[test]
+fn a_frozen_voice_stays_the_oldest_to_steal() {
+    let mut host = Host::new();
+    host.set("max_voices", 2f32).note_on(0, 60, 100).run(4800);
+    // a note struck after the freeze plays on unfrozen, while the frozen one stays older
+    // however long it is held
+    host.set("freeze", 1f32).run(64);
+    host.note_on(0, 64, 100).render(SAMPLERATE as usize, 512);
+    host.note_on(0, 67, 100).run(64);
+    let notes = host.synth().active_tones.values().map(|tone| tone.note).collect::<Vec<_>>();
+    assert_eq!(notes.len(), 2);
+    assert!(!notes.contains(&60) && notes.contains(&64), "{:?}", notes);
+}
+
+// what a layer adds to the left and right output of a note, given its level and pan ports
+fn layer(level_port: &str, pan_port: &str, pan: f32) -> (Vec<f32>, Vec<f32>) {
+    let play = |level: f32| {
+        let mut host = Host::new();
+        host.set(level_port, level).set(pan_port, pan);
+        host.note_on(0, 69, 100).run(4800)
+    };
+    let ((left, right), (plain_left, plain_right)) = (play(1f32), play(0f32));
+    let added = |with: Vec<f32>, without: Vec<f32>| {
+        with.iter().zip(without.iter()).map(|(a, b)| a - b).collect::<Vec<_>>()
+    };
+    (added(left, plain_left), added(right, plain_right))
+}
+
+#[test]
+fn each_layer_pans_on_its_own() {
+    let (left, right) = layer("noise_level", "noise_pan", -1f32);
+    assert!(rms(&left) > 1e-3 && peak(&right) == 0f32);
+    let (left, right) = layer("sub_level", "sub_pan", 1f32);
+    assert!(peak(&left) == 0f32 && rms(&right) > 1e-3);
+    // centred, a layer is the same on both sides
+    for (level_port, pan_port) in [("noise_level", "noise_pan"), ("sub_level", "sub_pan")] {
+        let (left, right) = layer(level_port, pan_port, 0f32);
+        assert!(rms(&left) > 1e-3);
+        assert_eq!(left, right);
+    }
+}
+
+// whether a second note glides under auto glide, struck while the first is held or after it
+fn auto_glides(legato: bool) -> bool {
+    let mut host = Host::new();
+    host.set("auto_glide", 1f32).set("portamento_time", 60f32);
+    host.note_on(0, 69, 100).run(64);
+    if !legato {
+        host.note_off(0, 69).render(SAMPLERATE as usize, 512);
+    }
+    host.note_on(0, 74, 100).run(64);
+    newest(&mut host).glide_frames > 0
+}
+
+#[test]
+fn auto_glide_only_glides_between_held_notes() {
+    assert!(auto_glides(true));
+    assert!(!auto_glides(false));
+    // the first note has nothing to glide from
+    let mut host = Host::new();
+    host.set("auto_glide", 1f32).set("portamento_time", 60f32).note_on(0, 69, 100).run(64);
+    assert_eq!(newest(&mut host).glide_frames, 0);
+}
+
+// frequency of a sine voice at 440 Hz bent all the way with the given 14 bit bend value
+fn fully_bent(bend: u16) -> f32 {
+    let mut host = Host::new();
+    host.set("brightness", 0f32).set("bend_up_range", 7f32).set("bend_down_range", 2f32);
+    host.note_on(0, 69, 100).midi(0, &[0xe0, (bend & 0x7f) as u8, (bend >> 7) as u8]);
+    host.run(4800);
+    spectrum::dominant_frequency(&host.run(8192).0, SAMPLERATE)
+}
+
+#[test]
+fn bends_up_and_down_span_their_own_ranges() {
+    let up = 440f32 * (7f32 * 8191f32 / 8192f32 / 12f32).exp2();
+    assert!((fully_bent(16383) - up).abs() < 1f32, "{}", fully_bent(16383));
+    let down = 440f32 * (-2f32 / 12f32).exp2();
+    assert!((fully_bent(0) - down).abs() < 1f32, "{}", fully_bent(0));
+    assert!((fully_bent(8192) - 440f32).abs() < 1f32);
+}
+
+// envelope level a note of `velocity` settles at after its decay, with a sustain of 0.5
+fn sustain_plateau(velocity_to_sustain: f32, velocity: u8) -> f32 {
+    let mut host = Host::new();
+    host.set("attack", 0f32).set("decay", 40f32).set("sustain", 63.5);
+    host.set("velocity_to_sustain", velocity_to_sustain).note_on(0, 69, velocity);
+    host.render(SAMPLERATE as usize / 2, 4800);
+    host.synth().voice_env_level(wmidi::Note::try_from(69).unwrap()).unwrap()
+}
+
+#[test]
+fn velocity_scales_the_sustain_level() {
+    let (soft, hard) = (sustain_plateau(0.5, 1), sustain_plateau(0.5, 127));
+    // scaled from 1 - 0.5 at the softest to 1 + 0.5 at the hardest
+    assert!((soft - 0.5 * (1f32 - 0.5 * (1f32 - 2f32 / 127f32))).abs() < 1e-4, "{}", soft);
+    assert!((hard - 0.75).abs() < 1e-4, "{}", hard);
+    // full modulation doubles the sustain of the hardest notes, here up to full level
+    assert_eq!(sustain_plateau(1f32, 127), 1f32);
+    assert_eq!(sustain_plateau(0f32, 1), sustain_plateau(0f32, 127));
+}
+
+#[test]
+fn the_cpu_throttle_lowers_the_voice_cap_with_the_cost() {
+    let mut host = Host::new();
+    host.set("cpu_throttle", 1f32).set("cpu_budget", 40f32).run(64);
+    // a plain voice costs one oscillator
+    assert_eq!(host.synth().voice_limit(), 40);
+    // the reverb takes its share of the budget first, and unison makes each voice dearer
+    host.set("reverb_mix", 0.5).run(64);
+    assert_eq!(host.synth().voice_limit(), 32);
+    host.set("unison", 4f32).run(64);
+    assert_eq!(host.synth().voice_limit(), 8);
+    for note in 40..60 {
+        host.note_on(0, note, 100);
+    }
+    host.run(64);
+    assert_eq!(host.voices(), 8);
+    // off, only max_voices caps them
+    host.set("cpu_throttle", 0f32).run(64);
+    assert_eq!(host.synth().voice_limit(), 128);
+}
+
+#[test]
+fn a_note_on_at_velocity_0_releases_the_note() {
+    let mut host = Host::new();
+    host.set("release", 80f32).note_on(0, 69, 100).run(480);
+    host.note_on(0, 69, 0).run(480);
+    assert_eq!(host.voices(), 1);
+    assert!(newest(&mut host).time_released.is_some());
+    host.render(SAMPLERATE as usize * 4, 4800);
+    assert_eq!(host.voices(), 0);
+    // without a held note it starts nothing
+    host.note_on(0, 72, 0).run(480);
+    assert_eq!(host.voices(), 0);
+}
+
+// detune offsets of 1000 unison copies of five voices, from a fresh instance
+fn detune_offsets(detune_distribution: f32) -> Vec<f32> {
+    let mut host = Host::new();
+    host.set("unison", 5f32).set("detune_distribution", detune_distribution).run(64);
+    let synth = host.synth();
+    (0..1000).map(|index| synth.unison_offset(index % 5)).collect()
+}
+
+#[test]
+fn each_detune_distribution_spreads_the_unison_as_it_should() {
+    assert_eq!(detune_offsets(0f32)[..5], [-1f32, -0.5, 0f32, 0.5, 1f32]);
+    for distribution in [1f32, 2f32] {
+        let offsets = detune_offsets(distribution);
+        // reproducible from the fixed seed, and within the detune range
+        assert_eq!(offsets, detune_offsets(distribution));
+        assert!(offsets.iter().all(|offset| (-1f32..=1f32).contains(offset)));
+        let mean = offsets.iter().sum::<f32>() / 1000f32;
+        assert!(mean.abs() < 0.05, "{}", mean);
+    }
+    // uniform offsets average half the range away from the centre, gaussian ones cluster
+    let spread =
+        |offsets: Vec<f32>| offsets.iter().map(|offset| offset.abs()).sum::<f32>() / 1000f32;
+    assert!((spread(detune_offsets(1f32)) - 0.5).abs() < 0.05);
+    assert!(spread(detune_offsets(2f32)) < 0.35);
+}
+
+// left and right output of a note at `pan` with the given output mode and mono fold law
+fn folded(pan: f32, output_mode: f32, mono_fold: f32) -> (Vec<f32>, Vec<f32>) {
+    let mut host = Host::new();
+    host.set("pan", pan).set("output_mode", output_mode).set("mono_fold", mono_fold);
+    host.note_on(0, 69, 100).run(4800)
+}
+
+#[test]
+fn a_mono_fold_keeps_a_panned_voice_at_the_centre_level() {
+    let (centre, _) = folded(0f32, 0f32, 0f32);
+    let (left, right) = folded(-1f32, 1f32, 0f32);
+    assert_eq!(left, right);
+    let difference = left.iter().zip(centre.iter()).map(|(a, b)| (a - b).abs());
+    assert!(difference.fold(0f32, f32::max) < 1e-6);
+    // the equal power fold plays the same material on both sides 3 dB louder
+    let (equal_power, _) = folded(-1f32, 1f32, 1f32);
+    assert!((rms(&equal_power) / rms(&centre) - std::f32::consts::SQRT_2).abs() < 1e-4);
+}
+
+// two seconds of a kicked filter at full resonance and a 1 kHz cutoff, without any input
+fn ringing_filter(self_osc: bool) -> Vec<f32> {
+    let coefficients = svf_coefficients(1000f32, 1f32, self_osc, SAMPLERATE);
+    let mut filter = Svf::default();
+    filter.kick();
+    (0..SAMPLERATE as usize * 2).map(|_| filter.lowpass(&coefficients, 0f32)).collect()
+}
+
+#[test]
+fn a_self_oscillating_filter_sustains_a_tone_at_its_cutoff() {
+    let ringing = ringing_filter(true);
+    let (early, late) = (&ringing[4800..13000], &ringing[ringing.len() - 8192..]);
+    assert!((rms(late) / rms(early) - 1f32).abs() < 0.01, "{} {}", rms(early), rms(late));
+    assert!(rms(late) > 0.1 && peak(&ringing) <= 1.5);
+    assert!((spectrum::dominant_frequency(late, SAMPLERATE) - 1000f32).abs() < 10f32);
+    // below self-oscillation the same kick dies away
+    let decaying = ringing_filter(false);
+    assert!(rms(&decaying[decaying.len() - 8192..]) < 1e-6);
+}
+
+#[test]
+fn the_time_ports_report_the_envelope_times_in_seconds() {
+    let mut host = Host::new();
+    host.set("attack", 50f32).set("decay", 70f32).set("release", 90f32).run(64);
+    let ports = [("attack_seconds", 50f32), ("decay_seconds", 70f32), ("release_seconds", 90f32)];
+    for (port, val) in ports {
+        let seconds = host.get(port);
+        assert!((seconds / midi_val_to_time(val) - 1f32).abs() < 1e-3, "{} {}", port, seconds);
+    }
+    // and follow the port within the next block
+    host.set("attack", 20f32).run(64);
+    assert!((host.get("attack_seconds") / midi_val_to_time(20f32) - 1f32).abs() < 1e-3);
+}
+
+// velocity stored for `note` struck at velocity 64, with a boost for low and for high notes
+fn boosted_velocity(note: u8, low_velocity_boost: f32, high_velocity_boost: f32) -> f32 {
+    let mut host = Host::new();
+    host.set("low_velocity_boost", low_velocity_boost);
+    host.set("high_velocity_boost", high_velocity_boost);
+    host.note_on(0, note, 64).run(64);
+    newest(&mut host).velocity
+}
+
+#[test]
+fn the_velocity_boost_follows_the_keyboard() {
+    let velocity = 64f32 / 127f32;
+    // a boost of 1 takes the square root of the velocity, interpolated over the note number
+    let expected = |note: u8| velocity.powf((-(1f32 - note as f32 / 127f32)).exp2());
+    assert!((boosted_velocity(0, 1f32, 0f32) - velocity.sqrt()).abs() < 1e-6);
+    assert!((boosted_velocity(127, 1f32, 0f32) - velocity).abs() < 1e-6);
+    assert!((boosted_velocity(36, 1f32, 0f32) - expected(36)).abs() < 1e-6);
+    assert!(boosted_velocity(36, 1f32, 0f32) > boosted_velocity(96, 1f32, 0f32));
+    assert_eq!(boosted_velocity(36, 0f32, 0f32), velocity);
+    assert_eq!(boosted_velocity(96, 0f32, 0f32), velocity);
+}
+
+// mono sum and left to right correlation of a bright note widened by `decorrelation`
+fn widened(decorrelation: f32) -> (Vec<f32>, f32) {
+    let mut host = Host::new();
+    host.set("brightness", 100f32).set("decorrelation", decorrelation);
+    host.note_on(0, 57, 100).run(4800);
+    let (left, right) = host.run(4800);
+    let sum = left.iter().zip(right.iter()).map(|(left, right)| left + right).collect();
+    (sum, correlation(&left, &right))
+}
+
+#[test]
+fn decorrelation_widens_without_changing_the_mono_sum() {
+    let (sum, narrow) = widened(0f32);
+    assert!((narrow - 1f32).abs() < 1e-6);
+    let mut previous = narrow;
+    for amount in [0.5, 1f32] {
+        let (widened_sum, correlation) = widened(amount);
+        assert!(correlation < previous - 0.1, "{} after {}", correlation, previous);
+        let difference = sum.iter().zip(widened_sum.iter()).map(|(a, b)| (a - b).abs());
+        assert!(difference.fold(0f32, f32::max) < 1e-5);
+        previous = correlation;
+    }
+}
+
+// phases of the four undetuned unison copies of a note as it starts, relative to the first
+fn unison_phases(unison_phase_spread: f32) -> Vec<f32> {
+    let mut host = Host::new();
+    host.set("unison", 4f32).set("unison_detune", 0f32);
+    host.set("unison_phase_spread", unison_phase_spread);
+    host.note_on(63, 69, 100).run(64);
+    let tone = newest(&mut host);
+    let first = tone.unison[0].phase;
+    let relative = |copy: &UnisonCopy| (copy.phase - first).rem_euclid(std::f32::consts::TAU);
+    tone.unison.iter().map(relative).collect()
+}
+
+#[test]
+fn the_phase_spread_spaces_the_unison_copies_evenly() {
+    let quarter = std::f32::consts::FRAC_PI_2;
+    for (spread, step) in [(1f32, quarter), (0.5, quarter / 2f32)] {
+        let phases = unison_phases(spread);
+        for (index, phase) in phases.iter().enumerate() {
+            assert!((phase - step * index as f32).abs() < 1e-4, "{:?}", phases);
+        }
+    }
+    assert_eq!(unison_phases(0f32), vec![0f32; 4]);
+}
+
+// level of a held note at `brightness`, with or without the compensation
+fn level_at_brightness(brightness: f32, brightness_comp: f32) -> f32 {
+    let mut host = Host::new();
+    host.set("brightness", brightness).set("brightness_comp", brightness_comp);
+    host.note_on(0, 69, 100).run(4800);
+    rms(&host.run(4800).0)
+}
+
+#[test]
+fn brightness_compensation_keeps_the_level_across_the_sweep() {
+    let sweep = |brightness_comp: f32| {
+        [0f32, 32f32, 64f32, 96f32, 120f32].map(|brightness| {
+            level_at_brightness(brightness, brightness_comp)
+        })
+    };
+    let range = |levels: [f32; 5]| {
+        let loudest = levels.iter().copied().fold(0f32, f32::max);
+        let softest = levels.iter().copied().fold(f32::MAX, f32::min);
+        20f32 * (loudest / softest).log10()
+    };
+    assert!(range(sweep(1f32)) < 0.1, "{:?}", sweep(1f32));
+    assert!(range(sweep(0f32)) > 6f32, "{:?}", sweep(0f32));
+}
+
+// energy of the left and of the right output of a note of `velocity`
+fn velocity_panned(velocity_to_pan: f32, velocity: u8) -> (f32, f32) {
+    let mut host = Host::new();
+    host.set("velocity_to_pan", velocity_to_pan).note_on(0, 69, velocity).run(4800);
+    let (left, right) = host.run(4800);
+    let energy = |output: &[f32]| output.iter().map(|sample| sample * sample).sum::<f32>();
+    (energy(&left), energy(&right))
+}
+
+#[test]
+fn velocity_places_notes_across_the_stereo_field_at_equal_power() {
+    for velocity in [20u8, 120] {
+        let (left, right) = velocity_panned(1f32, velocity);
+        let (centre_left, centre_right) = velocity_panned(0f32, velocity);
+        assert_eq!(centre_left, centre_right);
+        assert!(((left + right) / (centre_left + centre_right) - 1f32).abs() < 1e-4);
+        // soft notes to the left and loud ones to the right
+        assert_eq!(left > right, velocity < 64, "{} {}", left, right);
+    }
+}
+
+// largest change from one sample to the next
+fn largest_step(output: &[f32]) -> f32 {
+    output.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0f32, f32::max)
+}
+
+// 0.2 s of a bright note with the dsf variant of each of its two halves
+fn variant_halves(first: f32, second: f32) -> Vec<f32> {
+    let mut host = Host::new();
+    host.set("brightness", 100f32).set("dsf_variant", first).note_on(0, 69, 100);
+    let mut output = host.run(4800).0;
+    output.extend(host.set("dsf_variant", second).run(4800).0);
+    output
+}
+
+#[test]
+fn switching_the_dsf_variant_mid_note_does_not_click() {
+    let (one_sided, two_sided) = (variant_halves(0f32, 0f32), variant_halves(1f32, 1f32));
+    let steady = largest_step(&one_sided[2400..]).max(largest_step(&two_sided[2400..]));
+    let switched = variant_halves(0f32, 1f32);
+    assert!(largest_step(&switched[2400..]) <= 1.05 * steady);
+    // once the fade is over the note plays the new variant alone
+    let settled = switched[7200..].iter().zip(&two_sided[7200..]);
+    assert!(settled.map(|(a, b)| (a - b).abs()).fold(0f32, f32::max) < 1e-4);
+    let faded = switched[4800..].iter().zip(&one_sided[4800..]);
+    assert!(faded.map(|(a, b)| (a - b).abs()).fold(0f32, f32::max) > 1e-2);
+}
+
+// the settled pitch of a fingered glide from 69 up a fifth to 76
+fn fifth_glide_target(octave_snap: f32) -> f32 {
+    let mut host = Host::new();
+    host.set("portamento_mode", 1f32).set("glide_octave_snap", octave_snap);
+    host.note_on(0, 69, 100);
+    host.run(64);
+    host.note_on(0, 76, 100);
+    host.render(SAMPLERATE as usize / 2, 512);
+    let glide = host.synth().voice_glide(wmidi::Note::try_from(76).unwrap()).unwrap();
+    assert!(!glide.gliding);
+    glide.pitch
+}
+
+#[test]
+fn octave_snap_glides_to_the_nearest_octave_of_the_note() {
+    let fifth = 440f32 * (7f32 / 12f32).exp2();
+    assert!((fifth_glide_target(0f32) - fifth).abs() < 0.01);
+    // an octave below the fifth is nearer to 440 than the fifth itself
+    assert!((fifth_glide_target(1f32) - fifth / 2f32).abs() < 0.01);
+}
+
+#[test]
+fn octave_snap_checks_the_direction_and_remembers_the_snapped_pitch() {
+    let fifth = 440f32 * (7f32 / 12f32).exp2();
+    let mut host = Host::new();
+    // the fifth up snaps to a fourth down, which an upwards only glide leaves alone
+    host.set("portamento_mode", 3f32).set("glide_direction", 1f32);
+    host.set("glide_octave_snap", 1f32);
+    host.note_on(0, 69, 100).note_off(64, 69).run(128);
+    host.note_on(0, 76, 100).run(64);
+    let glide = host.synth().voice_glide(wmidi::Note::try_from(76).unwrap()).unwrap();
+    assert!(!glide.gliding);
+    assert!((glide.pitch - fifth / 2f32).abs() < 0.01);
+    // the next note glides from the snapped pitch, not from the fifth
+    let increment = host.synth().midi_note_to_phase_increment(wmidi::Note::try_from(76).unwrap());
+    let snapped = increment / 2f32;
+    assert!((host.synth().last_pitch.unwrap() / snapped - 1f32).abs() < 1e-3);
+    assert!((host.synth().last_voice.unwrap().1 / snapped - 1f32).abs() < 1e-3);
+}
+
+// the attack, decay and release in seconds for an envelope time and ratio
+fn envelope_segments(env_time: f32, env_ratio: f32) -> [f32; 3] {
+    let mut host = Host::new();
+    host.set("attack", 40f32).set("decay", 50f32).set("release", 60f32);
+    host.set("env_time", env_time).set("env_ratio", env_ratio).run(64);
+    ["attack_seconds", "decay_seconds", "release_seconds"].map(|port| host.get(port))
+}
+
+#[test]
+fn env_time_scales_every_segment_in_proportion() {
+    let unscaled = envelope_segments(1f32, 0f32);
+    for env_time in [0.25f32, 2f32, 4f32] {
+        let scaled = envelope_segments(env_time, 0f32);
+        for (scaled, unscaled) in scaled.iter().zip(&unscaled) {
+            assert!((scaled / unscaled - env_time).abs() < 1e-4, "{} at {}", scaled, env_time);
+        }
+    }
+    // a ratio ties the release to the attack, and the knob still scales both
+    let [attack, _, release] = envelope_segments(2f32, 3f32);
+    assert!((attack - 2f32 * unscaled[0]).abs() < 1e-6);
+    assert!((release - 3f32 * attack).abs() < 1e-6);
+}
+
+// raw oscillator output of a held note with a slow attack at a gain
+fn raw_note(gain: f32) -> Vec<f32> {
+    let mut host = Host::new();
+    host.set("raw_osc", 1f32).set("brightness", 30f32).set("attack", 60f32).set("gain", gain);
+    host.note_on(0, 69, 40);
+    host.run(8192).0
+}
+
+#[test]
+fn raw_osc_outputs_the_oscillator_without_envelope_or_gain() {
+    let raw = raw_note(0f32);
+    assert_eq!(raw, raw_note(-24f32));
+    assert!((spectrum::dominant_frequency(&raw, SAMPLERATE) - 440f32).abs() < 1f32);
+    // steady from the first cycle on, though the envelope is still far from its peak
+    let cycle = 109usize;
+    assert!(peak(&raw[..cycle]) > 0.1);
+    assert!((peak(&raw[..cycle]) / peak(&raw[8192 - cycle..]) - 1f32).abs() < 0.01);
+    assert!((rms(&raw[..4096]) / rms(&raw[4096..]) - 1f32).abs() < 0.01);
+}
+
+// level of a detuneless unison note with spread copy phases, folded to mono
+fn mono_unison_level(phase_align: f32) -> f32 {
+    let mut host = Host::new();
+    host.set("unison", 4f32).set("unison_detune", 0f32).set("unison_phase_spread", 1f32);
+    host.set("output_mode", 1f32).set("brightness", 64f32).set("phase_align", phase_align);
+    host.note_on(0, 69, 100);
+    rms(&host.run(4800).0[2400..])
+}
+
+#[test]
+fn phase_alignment_keeps_unison_copies_from_cancelling_in_mono() {
+    let (spread, aligned) = (mono_unison_level(0f32), mono_unison_level(1f32));
+    assert!(aligned > 2f32 * spread, "{} against {}", aligned, spread);
+}
+
+// frames a note takes to ring out after its note-off with the damper pedal at `pedal`
+fn half_pedal_ring_time(pedal: u8) -> usize {
+    let mut host = Host::new();
+    host.set("half_pedal", 1f32).set("release", 60f32).cc(0, 64, pedal);
+    host.note_on(0, 69, 100);
+    host.run(480);
+    host.note_off(0, 69);
+    let mut frames = 0usize;
+    while host.voices() > 0 {
+        host.run(64);
+        frames += 64;
+    }
+    frames
+}
+
+#[test]
+fn half_pedaling_lengthens_the_release_with_the_pedal_position() {
+    let released = half_pedal_ring_time(0) as f32;
+    for pedal in [32u8, 64u8, 96u8] {
+        let expected = HALF_PEDAL_MAX_RELEASE_SCALE.powf(pedal as f32 / 127f32);
+        let scale = half_pedal_ring_time(pedal) as f32 / released;
+        assert!((scale / expected - 1f32).abs() < 0.05, "{} at {}", scale, pedal);
+    }
+}
+
+// both channels of a note with the haas delay at `haas` milliseconds
+fn haas_note(haas: f32, note: u8) -> (Vec<f32>, Vec<f32>) {
+    let mut host = Host::new();
+    host.set("haas", haas).note_on(0, note, 100);
+    host.run(4800)
+}
+
+#[test]
+fn haas_delays_one_channel_by_the_amount_for_the_note() {
+    let (left, right) = haas_note(0f32, 28);
+    assert_eq!(left, right);
+    // 32 semitones below the centre spreads half way, so 10 ms delay the right by 5 ms
+    let delay = 240usize;
+    let (left, right) = haas_note(10f32, 28);
+    assert!(right[..delay].iter().all(|sample| *sample == 0f32));
+    assert!(peak(&left[..delay]) > 0.01);
+    let offset = left.iter().zip(&right[delay..]).map(|(left, right)| (left - right).abs());
+    assert!(offset.fold(0f32, f32::max) < 1e-6);
+    // and as far above it, the left channel
+    let (left, right) = haas_note(10f32, 28 + 64);
+    let offset = right.iter().zip(&left[delay..]).map(|(right, left)| (right - left).abs());
+    assert!(offset.fold(0f32, f32::max) < 1e-6);
+}
+
+#[test]
+fn a_note_off_on_another_channel_leaves_the_note_sounding() {
+    let mut host = Host::new();
+    host.set("omni", 1f32).set("release", 10f32);
+    host.midi(0, &[0x90, 60, 100]);
+    host.run(480);
+    // the same note on the second channel
+    host.midi(0, &[0x81, 60, 64]);
+    host.run(4800);
+    assert_eq!(host.voices(), 1);
+    assert!(newest(&mut host).time_released.is_none());
+    assert!(peak(&host.run(480).0) > 0.01);
+    host.midi(0, &[0x80, 60, 64]).render(SAMPLERATE as usize, 512);
+    assert_eq!(host.voices(), 0);
+}
+
+// the brightness the voices use while the mod wheel flips between its ends every 5 ms
+fn square_modulated_brightness(brightness_slew: f32) -> Vec<f32> {
+    let mut host = Host::new();
+    host.set("brightness", 0f32).set("brightness_slew", brightness_slew);
+    host.note_on(0, 69, 100).run(4800);
+    let mut slewed = Vec::new();
+    for flip in 0..20 {
+        host.cc(0, 1, if flip % 2 == 0 { 127 } else { 0 });
+        for _ in 0..240 {
+            host.run(1);
+            slewed.push(host.synth().slewed_brightness);
+        }
+    }
+    slewed
+}
+
+// how far a brightness swings over its last cycle of modulation
+fn swing(brightness: &[f32]) -> f32 {
+    let cycle = &brightness[brightness.len() - 480..];
+    let top = cycle.iter().fold(0f32, |top, value| top.max(*value));
+    top - cycle.iter().fold(1f32, |bottom, value| bottom.min(*value))
+}
+
+#[test]
+fn brightness_slew_limits_square_wave_modulation() {
+    let slewed = square_modulated_brightness(20f32);
+    let limit = 20f32 / SAMPLERATE;
+    assert!(slewed.windows(2).all(|pair| (pair[1] - pair[0]).abs() <= limit * 1.001));
+    // so it swings by at most a tenth of the range in each half cycle
+    assert!(swing(&slewed) <= 240f32 * limit * 1.001);
+    let unlimited = square_modulated_brightness(10000f32);
+    assert!(swing(&unlimited) > 2f32 * swing(&slewed), "{}", swing(&unlimited));
+}
+
+#[test]
+fn hold_last_keeps_the_last_note_sounding_after_its_release() {
+    let mut host = Host::new();
+    host.set("hold_last", 1f32);
+    host.note_on(0, 69, 100).run(480);
+    host.note_off(0, 69).render(SAMPLERATE as usize, 512);
+    assert_eq!(host.voices(), 1);
+    let held = newest(&mut host);
+    assert!(held.time_released.is_none());
+    assert!(peak(&host.run(480).0) > 0.01);
+    // a new note takes over, and is then the one held
+    host.note_on(0, 72, 100).run(480);
+    host.note_off(0, 72).render(SAMPLERATE as usize, 512);
+    assert_eq!(host.voices(), 1);
+    assert_eq!(newest(&mut host).note, 72);
+    // turning the toggle off lets it go
+    host.set("hold_last", 0f32).render(SAMPLERATE as usize, 512);
+    assert_eq!(host.voices(), 0);
+}
+
+// velocities stored for 16 repeats of the same note at velocity 64
+fn humanized_velocities(velocity_humanize: f32) -> Vec<f32> {
+    let mut host = Host::new();
+    host.set("velocity_humanize", velocity_humanize);
+    (0..16)
+        .map(|_| {
+            host.note_on(0, 69, 64).run(64);
+            let velocity = newest(&mut host).velocity;
+            host.note_off(0, 69).render(SAMPLERATE as usize / 4, 512);
+            velocity
+        })
+        .collect()
+}
+
+#[test]
+fn velocity_humanization_varies_repeated_notes_within_its_range() {
+    let velocity = 64f32 / 127f32;
+    assert!(humanized_velocities(0f32).iter().all(|stored| *stored == velocity));
+    let humanized = humanized_velocities(0.1);
+    assert!(humanized.iter().all(|stored| (stored - velocity).abs() <= 0.1));
+    let spread = humanized.iter().fold(0f32, |top, stored| top.max(*stored))
+        - humanized.iter().fold(1f32, |bottom, stored| bottom.min(*stored));
+    assert!(spread > 0.05, "{:?}", humanized);
+}
+
+// peaks of the front left, front right, rear left and rear right outputs of a held note
+#[cfg(feature = "surround")]
+fn surround_peaks(pan: f32, front_back: f32) -> [f32; 4] {
+    let mut host = Host::new();
+    host.set("pan", pan).set("pan_law", 1f32).set("front_back", front_back);
+    host.note_on(0, 69, 100).run(4800);
+    let (left, right) = host.run(4800);
+    let (rear_left, rear_right) =
+        (host.output("rear_left_audio_output", 4800), host.output("rear_right_audio_output", 4800));
+    [left, right, rear_left, rear_right].map(|output| peak(&output))
+}
+
+#[cfg(feature = "surround")]
+#[test]
+fn front_back_places_voices_between_the_front_and_the_rear() {
+    let [level, ..] = surround_peaks(-1f32, -1f32);
+    let expected = |pan: f32, front_back: f32, gains: [f32; 4]| {
+        for (peak, gain) in surround_peaks(pan, front_back).iter().zip(gains) {
+            assert!((peak - level * gain).abs() < 1e-3 * level, "{} {}", pan, front_back);
+        }
+    };
+    let half = std::f32::consts::FRAC_1_SQRT_2;
+    expected(-1f32, -1f32, [1f32, 0f32, 0f32, 0f32]);
+    expected(1f32, 1f32, [0f32, 0f32, 0f32, 1f32]);
+    // the centre of the field shares the note equally between the four corners
+    expected(0f32, 0f32, [0.5, 0.5, 0.5, 0.5]);
+    expected(-1f32, 0f32, [half, 0f32, half, 0f32]);
+}
+
+#[test]
+fn the_pitch_envelope_sweeps_the_note_down_to_its_pitch() {
+    let mut host = Host::new();
+    host.set("raw_osc", 1f32).set("brightness", 0f32);
+    host.set("pitch_env_amount", 12f32).set("pitch_env_time", 100f32);
+    let output = host.note_on(0, 69, 100).run(8192).0;
+    let crossings = output
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] < 0f32 && pair[1] >= 0f32)
+        .map(|(frame, pair)| frame as f32 + pair[0] / (pair[0] - pair[1]))
+        .collect::<Vec<_>>();
+    // an octave above at the onset, falling linearly in semitones over the 4800 frames
+    let sweep = |frame: f32| 440f32 * (1f32 - frame / 4800f32).max(0f32).exp2();
+    for pair in crossings.windows(2) {
+        let frequency = SAMPLERATE / (pair[1] - pair[0]);
+        let expected = sweep((pair[0] + pair[1]) / 2f32);
+        assert!((frequency / expected - 1f32).abs() < 0.01, "{} at {}", frequency, pair[0]);
+    }
+    assert!(crossings[0] < 100f32 && *crossings.last().unwrap() > 8000f32);
+}
+
+#[test]
+fn a_note_after_reactivation_plays_as_after_instantiation() {
+    let settings = |host: &mut Host, gain: f32| {
+        host.set("gain", gain).set("pan", 0.5).set("brightness", 20f32);
+        host.set("delay_mix", 0.5).set("delay_time", 50f32);
+    };
+    let mut fresh = Host::new();
+    settings(&mut fresh, -12f32);
+    let (fresh_left, fresh_right) = fresh.note_on(0, 69, 100).run(4800);
+    assert!(peak(&fresh_left) > 0.01);
+    let mut host = Host::new();
+    settings(&mut host, 0f32);
+    host.note_on(0, 69, 100).run(4800);
+    // a gain changed while inactive applies at once instead of gliding, and neither the old
+    // note nor its echoes carry over
+    host.reactivate();
+    settings(&mut host, -12f32);
+    let (left, right) = host.note_on(0, 69, 100).run(4800);
+    let difference =
+        |a: &[f32], b: &[f32]| a.iter().zip(b).fold(0f32, |d, (a, b)| d.max((a - b).abs()));
+    assert!(difference(&left, &fresh_left) < 1e-6 && difference(&right, &fresh_right) < 1e-6);
+}
+
+// frames a fingered glide from 69 to 74 struck at `velocity` takes
+fn velocity_glide_frames(velocity_to_glide: f32, velocity: u8) -> usize {
+    let mut host = Host::new();
+    host.set("portamento_mode", 1f32).set("velocity_to_glide", velocity_to_glide);
+    host.note_on(0, 69, 100).run(64);
+    host.note_on(0, 74, velocity);
+    let to = wmidi::Note::try_from(74).unwrap();
+    let mut frames = 0usize;
+    loop {
+        host.run(16);
+        frames += 16;
+        if !host.synth().voice_glide(to).unwrap().gliding {
+            return frames;
+        }
+    }
+}
+
+#[test]
+fn harder_notes_glide_faster_with_velocity_to_glide() {
+    let soft = velocity_glide_frames(1f32, 20) as f32;
+    let hard = velocity_glide_frames(1f32, 120) as f32;
+    let expected = VELOCITY_GLIDE_MAX_SCALE.powf(2f32 * (120f32 - 20f32) / 127f32);
+    assert!((soft / hard / expected - 1f32).abs() < 0.05, "{} against {}", soft / hard, expected);
+    // without it the velocity leaves the glide alone
+    assert_eq!(velocity_glide_frames(0f32, 20), velocity_glide_frames(0f32, 120));
+}
+
+#[test]
+fn a_single_unison_voice_renders_the_plain_oscillator() {
+    let render = |detune: f32, spread: f32| {
+        let mut host = Host::new();
+        host.set("brightness", 64f32).set("unison", 1f32).set("unison_detune", detune);
+        host.set("unison_phase_spread", spread).set("raw_osc", 1f32).note_on(0, 69, 100);
+        let output = host.run(4800).0;
+        (output, newest(&mut host))
+    };
+    let (output, tone) = render(0f32, 0f32);
+    assert!(tone.unison.is_empty());
+    // the dsf sum evaluated at a phase starting from 0, with no unison scaling
+    let w = 64f32 / 127f32;
+    let mut phase = 0f32;
+    for (frame, sample) in output.iter().enumerate() {
+        assert_eq!(*sample, dsf_inf(w, phase, phase), "at {}", frame);
+        phase = (phase + tone.phase_increment).rem_euclid(std::f32::consts::TAU);
+    }
+    // the unison settings have nothing to act on
+    assert_eq!(render(50f32, 1f32).0, output);
+}
+
+#[test]
+fn the_follower_tracks_the_envelope_of_the_input() {
+    let mut host = Host::new();
+    host.set("follower_attack", 10f32).set("follower_release", 50f32);
+    host.run(480);
+    assert_eq!(host.get("follower"), 0f32);
+    // a pulse of half scale for 0.1 s, fed 10 ms at a time
+    let (pulse, silence) = ([0.5; 480], [0f32; 480]);
+    host.input("left_audio_input", &pulse).input("right_audio_input", &pulse).run(480);
+    let attacked = 0.5 * (1f32 - (-1f32).exp());
+    assert!((host.get("follower") - attacked).abs() < 1e-3, "{}", host.get("follower"));
+    host.render(4320, 480);
+    assert!((host.get("follower") - 0.5).abs() < 1e-3);
+    host.input("left_audio_input", &silence).input("right_audio_input", &silence);
+    host.render(2400, 480);
+    let released = 0.5 * (-1f32).exp();
+    assert!((host.get("follower") - released).abs() < 1e-3, "{}", host.get("follower"));
+    host.render(SAMPLERATE as usize, 480);
+    assert!(host.get("follower") < 1e-3);
+}
+
+// lfo phase and value after each of the first 0.3125 s of a 10 Hz sample-and-hold lfo
+// the note, press and release frames of every voice the arpeggiator plays over a chord held
+// into the fifth step of 6000 frames, at 120 bpm and four steps per beat
+fn arpeggiated_notes(gate_length: f32) -> Vec<(u8, u64, Option<u64>)> {
+    let mut host = Host::new();
+    host.set("host_bpm", 120f32).set("arp", 1f32).set("gate_length", gate_length);
+    // a release long enough for every voice to be seen after its note-off
+    host.set("release", 60f32);
+    host.note_on(0, 67, 100).note_on(0, 60, 100).note_on(0, 64, 100);
+    let mut notes = std::collections::BTreeMap::new();
+    for block in 0..65 {
+        // letting go stops the steps, but the note sounding still gets its full gate
+        if block == 50 {
+            host.note_off(0, 60).note_off(0, 64).note_off(0, 67);
+        }
+        host.run(512);
+        for (voice, tone) in &host.synth().active_tones {
+            notes.insert(*voice, (tone.note, tone.time_pressed, tone.time_released));
+        }
+    }
+    notes.into_values().collect()
+}
+
+#[test]
+fn the_arpeggiator_releases_each_note_after_the_gate_length() {
+    for (gate_length, gate_frames) in [(0.5, 3000u64), (0.25, 1500), (1f32, 6000)] {
+        let notes = arpeggiated_notes(gate_length);
+        assert_eq!(notes.len(), 5, "{}", gate_length);
+        for (step, &(note, pressed, released)) in notes.iter().enumerate() {
+            let step = step as u64;
+            assert_eq!(note, [60, 64, 67, 60, 64][step as usize]);
+            assert_eq!(pressed, step * 6000);
+            assert_eq!(released, Some(step * 6000 + gate_frames), "{} {}", gate_length, step);
+        }
+    }
+    // the default sounds each note for half its step
+    assert_eq!(Host::new().get("gate_length"), 0.5);
+}
+
+fn sample_and_hold_lfo() -> Vec<(f32, f32)> {
+    let mut host = Host::new();
+    host.set("lfo_shape", 2f32).set("lfo_rate", 10f32).note_on(0, 69, 100);
+    (0..15000)
+        .map(|_| {
+            host.run(1);
+            let synth = host.synth();
+            (synth.lfo_phase, lfo_value(synth.lfo_shape, synth.lfo_phase, synth.lfo_held))
+        })
+        .collect()
+}
+
+#[test]
+fn the_sample_and_hold_lfo_steps_once_per_cycle() {
+    let lfo = sample_and_hold_lfo();
+    let mut cycles = 0usize;
+    for pair in lfo.windows(2) {
+        let ((phase, value), (next_phase, next_value)) = (pair[0], pair[1]);
+        if next_phase < phase {
+            cycles += 1;
+            assert_ne!(next_value, value);
+        } else {
+            assert_eq!(next_value, value);
+        }
+        assert!((-1f32..=1f32).contains(&next_value));
+    }
+    assert_eq!(cycles, 3);
+    // drawn from the seeded generator, so every instance steps through the same values
+    assert_eq!(sample_and_hold_lfo(), lfo);
+}
@@ -0,0 +1,106 @@
+// spectral analysis for the tests: a plain radix-2 fft over a hann windowed buffer, so that
+// tests can check pitch and harmonic content without a dependency
+
+// magnitude of each bin from 0 Hz up to half the samplerate; the buffer is windowed and
+// padded with silence to the next power of two, so bin `i` lies at i * samplerate / length
+pub fn magnitudes(buf: &[f32]) -> Vec<f32> {
+    let length = buf.len().next_power_of_two();
+    let mut real = vec![0f64; length];
+    let mut imaginary = vec![0f64; length];
+    for (index, sample) in buf.iter().enumerate() {
+        let window = 0.5 - 0.5 * (std::f64::consts::TAU * index as f64 / buf.len() as f64).cos();
+        real[index] = *sample as f64 * window;
+    }
+    fft(&mut real, &mut imaginary);
+    (0..=length / 2)
+        .map(|bin| (real[bin] * real[bin] + imaginary[bin] * imaginary[bin]).sqrt() as f32)
+        .collect()
+}
+
+// frequency of a bin of the magnitudes of a buffer of `frames`
+pub fn bin_frequency(bin: f32, frames: usize, samplerate: f32) -> f32 {
+    bin * samplerate / frames.next_power_of_two() as f32
+}
+
+// frequency of the strongest bin, refined between its neighbours by a parabola through the
+// log magnitudes
+pub fn dominant_frequency(buf: &[f32], samplerate: f32) -> f32 {
+    let magnitudes = magnitudes(buf);
+    let peak = (1..magnitudes.len() - 1)
+        .max_by(|&a, &b| magnitudes[a].total_cmp(&magnitudes[b]))
+        .unwrap();
+    let [left, centre, right] =
+        [peak - 1, peak, peak + 1].map(|bin| (magnitudes[bin] as f64).max(1e-30).ln());
+    let offset = 0.5 * (left - right) / (left - 2f64 * centre + right);
+    bin_frequency(peak as f32 + offset as f32, buf.len(), samplerate)
+}
+
+// share of the energy of the buffer that lies above `hz`, from 0 to 1
+pub fn energy_above(buf: &[f32], samplerate: f32, hz: f32) -> f32 {
+    let magnitudes = magnitudes(buf);
+    let (mut above, mut total) = (0f64, 0f64);
+    for (bin, magnitude) in magnitudes.iter().enumerate() {
+        let energy = *magnitude as f64 * *magnitude as f64;
+        total += energy;
+        if bin_frequency(bin as f32, buf.len(), samplerate) > hz {
+            above += energy;
+        }
+    }
+    if total > 0f64 {
+        (above / total) as f32
+    } else {
+        0f32
+    }
+}
+
+// in-place iterative fft; the length must be a power of two
+fn fft(real: &mut [f64], imaginary: &mut [f64]) {
+    let length = real.len();
+    let mut j = 0usize;
+    for i in 1..length {
+        let mut bit = length >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+    let mut size = 2usize;
+    while size <= length {
+        let angle = -std::f64::consts::TAU / size as f64;
+        for start in (0..length).step_by(size) {
+            for k in 0..size / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + size / 2);
+                let twiddled_real = real[b] * cos - imaginary[b] * sin;
+                let twiddled_imaginary = real[b] * sin + imaginary[b] * cos;
+                real[b] = real[a] - twiddled_real;
+                imaginary[b] = imaginary[a] - twiddled_imaginary;
+                real[a] += twiddled_real;
+                imaginary[a] += twiddled_imaginary;
+            }
+        }
+        size *= 2;
+    }
+}
+
+#[test]
+fn a_pure_sine_has_its_frequency_and_no_energy_above_it() {
+    let samplerate = 48000f32;
+    for frequency in [110f32, 440f32, 1234.5f32, 9000f32] {
+        let sine = (0..8192)
+            .map(|index| (std::f32::consts::TAU * frequency * index as f32 / samplerate).sin())
+            .collect::<Vec<_>>();
+        let dominant = dominant_frequency(&sine, samplerate);
+        assert!((dominant - frequency).abs() < 1f32, "{} at {}", dominant, frequency);
+        assert!(energy_above(&sine, samplerate, frequency * 1.1) < 1e-3);
+        assert!(energy_above(&sine, samplerate, frequency * 0.9) > 0.999);
+        let peak = magnitudes(&sine).iter().fold(0f32, |peak, bin| peak.max(*bin));
+        // a unit sine peaks at half the length, halved again by the mean of the window
+        assert!((peak / 8192f32 - 0.25).abs() < 0.05);
+    }
+}